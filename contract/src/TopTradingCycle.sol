@@ -19,11 +19,16 @@ contract TopTradingCycle is ITopTradingCycle, ERC721Holder, Ownable, ReentrancyG
     bytes32 public constant imageID = ImageID.PROVABLE_TTC_ID;
 
     IRiscZeroVerifier public immutable verifier;
+    Mechanism public immutable mechanism;
+    // Digest of the chain spec the guest must run under, which Steel commits to as `configID`
+    bytes32 public immutable configID;
     
     Phase public currentPhase;
     uint256 public phaseDuration;
     uint256 public phaseStartTimestamp;
     uint256 public tradeInitiatedAtBlock;
+    // Outcome the guest committed, set once the reallocation is submitted
+    TradeStatus public tradeStatus;
 
     // Mapping from token hash to current owner
     mapping(bytes32 => address) public tokenOwners;
@@ -41,10 +46,21 @@ contract TopTradingCycle is ITopTradingCycle, ERC721Holder, Ownable, ReentrancyG
      * @dev Constructor sets the verifier contract address and phase duration
      * @param _verifier Address of the Verifier contract
      * @param _phaseDuration Duration of each phase in seconds
+     * @param _mechanism Allocation rule used to reallocate the tokens
+     * @param _configID Digest of the chain spec (chain ID and hard forks) proofs must be made with
      */
-    constructor(IRiscZeroVerifier _verifier, uint256 _phaseDuration) Ownable(msg.sender) {
+    constructor(
+        IRiscZeroVerifier _verifier,
+        uint256 _phaseDuration,
+        Mechanism _mechanism,
+        bytes32 _configID
+    )
+        Ownable(msg.sender)
+    {
         require(address(_verifier) != address(0), "Invalid Verifier address");
         verifier = _verifier;
+        mechanism = _mechanism;
+        configID = _configID;
         phaseDuration = _phaseDuration;
         currentPhase = Phase.Deposit;
         phaseStartTimestamp = block.timestamp;
@@ -269,6 +285,16 @@ contract TopTradingCycle is ITopTradingCycle, ERC721Holder, Ownable, ReentrancyG
         return journal;
     }
 
+    /**
+     * @dev Parse batch journal data from bytes into a BatchJournal struct
+     * @param batchJournalData The ABI encoded batch journal data
+     * @return batch The decoded BatchJournal struct
+     */
+    function parseBatchJournal(bytes calldata batchJournalData) public pure returns (BatchJournal memory) {
+        BatchJournal memory batch = abi.decode(batchJournalData, (BatchJournal));
+        return batch;
+    }
+
     /**
      * @dev Reallocate token ownership according to the computed trading cycles
      * For each (collection, tokenId, newOwner) triplet, newOwner becomes the owner of the token.
@@ -284,15 +310,53 @@ contract TopTradingCycle is ITopTradingCycle, ERC721Holder, Ownable, ReentrancyG
     function reallocateTokens(bytes calldata journalData, bytes calldata seal) external onlyInPhase(Phase.Trade) {
         // Decode and validate the journal data
         Journal memory journal = parseJournal(journalData);
-        require(journal.ttcContract == address(this), "Invalid contract address");
-        (uint240 claimID,) = Encoding.decodeVersionedID(journal.commitment.id);
-        require(claimID == tradeInitiatedAtBlock, "Commitment doesn't represent state at trade block number");
-        require(Steel.validateCommitment(journal.commitment), "Invalid commitment");
+        _validateJournal(journal);
 
         // Verify the proof
         bytes32 journalHash = sha256(journalData);
         verifier.verify(seal, imageID, journalHash);
 
+        _settle(journal);
+    }
+
+    /**
+     * @dev Reallocate token ownership from a proof that covers several pools at once. The
+     * proof commits to every pool's journal, and this contract only applies its own.
+     * 
+     * @param batchJournalData bytes representing the abi encoded batch journal
+     * @param index Position of this contract's journal in the batch
+     * @param seal The verification seal from RISC Zero
+     */
+    function reallocateTokensFromBatch(bytes calldata batchJournalData, uint256 index, bytes calldata seal)
+        external
+        onlyInPhase(Phase.Trade)
+    {
+        BatchJournal memory batch = parseBatchJournal(batchJournalData);
+        require(index < batch.journals.length, "Batch index out of bounds");
+        Journal memory journal = batch.journals[index];
+        _validateJournal(journal);
+
+        // Verify the proof
+        bytes32 journalHash = sha256(batchJournalData);
+        verifier.verify(seal, imageID, journalHash);
+
+        _settle(journal);
+    }
+
+    // Check that the journal was produced for this contract's pool at the trade block, on this
+    // chain and under its hard fork schedule
+    function _validateJournal(Journal memory journal) internal view {
+        require(journal.ttcContract == address(this), "Invalid contract address");
+        require(journal.chainId == block.chainid, "Journal was produced for a different chain");
+        require(journal.commitment.configID == configID, "Journal was produced with a different chain spec");
+        require(journal.mechanism == mechanism, "Journal was produced by a different mechanism");
+        (uint240 claimID,) = Encoding.decodeVersionedID(journal.commitment.id);
+        require(claimID == tradeInitiatedAtBlock, "Commitment doesn't represent state at trade block number");
+        require(Steel.validateCommitment(journal.commitment), "Invalid commitment");
+    }
+
+    // Apply a verified journal's reallocations and move on to withdrawals
+    function _settle(Journal memory journal) internal {
         for (uint256 i = 0; i < journal.reallocations.length; i++) {
             TokenReallocation memory realloc = journal.reallocations[i];
             bytes32 tokenHash = realloc.tokenHash;
@@ -300,6 +364,8 @@ contract TopTradingCycle is ITopTradingCycle, ERC721Holder, Ownable, ReentrancyG
             
             _transferNFTOwnership(currentOwner, realloc.newOwner, tokenHash);
         }
+        tradeStatus = journal.status;
+        emit TradeSettled(journal.status, journal.preferencesDigest);
         currentPhase = Phase.Withdraw;
     }
 
@@ -19,6 +19,24 @@ interface ITopTradingCycle {
         Closed
     }
 
+    // The allocation rule the guest runs; must match the dispatch in the guest.
+    // RandomSerialDictatorship draws its order from the hash of the block that moved the pool
+    // to Trade, which the journal's Steel commitment binds and nobody knows while ranking.
+    enum Mechanism {
+        TopTradingCycle,
+        SerialDictatorship,
+        RandomSerialDictatorship
+    }
+
+    // What the guest could do with the pool; anything but Success leaves every token with its
+    // depositor. Must match the status constants in the guest
+    enum TradeStatus {
+        Success,
+        EmptyPool,
+        InvalidPreferences,
+        UnknownMechanism
+    }
+
     // Structs - must match exactly with implementation
     struct Token {
         address collection;
@@ -39,15 +57,31 @@ interface ITopTradingCycle {
     struct Journal {
         Steel.Commitment commitment;
         address ttcContract;
+        uint256 chainId;
+        Mechanism mechanism;
+        TradeStatus status;
+        // keccak256(abi.encode(getAllTokenPreferences())) at the trade block, and how many
+        // distinct depositors it lists
+        bytes32 preferencesDigest;
+        uint256 participantCount;
         TokenReallocation[] reallocations;
     }
 
+    // One journal per pool, committed by a proof that covers several pools
+    struct BatchJournal {
+        Journal[] journals;
+    }
+
     // Events
     event PhaseChanged(Phase newPhase);
+    event TradeSettled(TradeStatus status, bytes32 preferencesDigest);
 
     // Constants and public state variables
     function imageID() external view returns (bytes32);
     function verifier() external view returns (IRiscZeroVerifier);
+    function mechanism() external view returns (Mechanism);
+    function configID() external view returns (bytes32);
+    function tradeStatus() external view returns (TradeStatus);
     function currentPhase() external view returns (Phase);
     function phaseDuration() external view returns (uint256);
     function phaseStartTimestamp() external view returns (uint256);
@@ -67,5 +101,7 @@ interface ITopTradingCycle {
     function getAllTokenPreferences() external view returns (TokenPreferences[] memory);
     function parseJournal(bytes calldata journalData) external pure returns (Journal memory);
     function reallocateTokens(bytes calldata journalData, bytes calldata seal) external;
+    function parseBatchJournal(bytes calldata batchJournalData) external pure returns (BatchJournal memory);
+    function reallocateTokensFromBatch(bytes calldata batchJournalData, uint256 index, bytes calldata seal) external;
     function getTokenFromHash(bytes32 tokenHash) external view returns (Token memory tokenData);
 }
\ No newline at end of file
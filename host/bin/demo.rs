@@ -3,7 +3,7 @@ use clap::Parser;
 use host::{
     actor::{self, Actor, TradeResults},
     checkpoint::{Checkpoint, Checkpointer},
-    cli::{Command, DemoConfig},
+    cli::{Command, DemoConfig, GraphConfig, GraphFormat},
     contract::{nft::TestNFT, ttc::ITopTradingCycle},
     env::{create_provider, init_console_subscriber},
     gas_metrics::{with_metrics, GasMetrics},
@@ -15,17 +15,29 @@ use proptest::{
     test_runner::TestRunner,
 };
 use rand::prelude::SliceRandom;
-use risc0_steel::alloy::{primitives::Bytes, sol_types::SolValue};
+use risc0_steel::alloy::primitives::Bytes;
 use risc0_steel::alloy::{
-    primitives::{utils::parse_ether, Address, U256},
+    primitives::{utils::parse_ether, Address, B256, U256},
     signers::local::PrivateKeySigner,
 };
-use std::{collections::HashMap, path::Path, str::FromStr, thread::sleep, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    str::FromStr,
+    thread::sleep,
+    time::Duration,
+};
 use tokio::sync::Mutex;
 use tracing::info;
-use ttc::strict::Preferences;
+use ttc::{
+    stats::AllocationStats,
+    strict::{Allocation, PreferenceGraph, Preferences},
+};
 use url::Url;
 
+// Must match the order of `ITopTradingCycle.Mechanism`
+const TOP_TRADING_CYCLE: u8 = 0;
+
 struct TestSetup {
     node_url: Url,
     config: DemoConfig,
@@ -220,31 +232,50 @@ impl TestSetup {
     }
 
     // Call the solver and submit the reallocation data to the contract
-    async fn reallocate(
-        &self,
-        proof: ITopTradingCycle::Journal,
-        seal: Vec<u8>,
-    ) -> Result<TradeResults> {
+    async fn reallocate(&self, proof: &monitor_api::types::Proof) -> Result<TradeResults> {
         let provider = create_provider(self.node_url.clone(), self.owner.clone());
         let ttc = ITopTradingCycle::new(self.ttc, provider);
-        let journal_data = Bytes::from(proof.abi_encode());
-        let realloc_tx = ttc
-            .reallocateTokens(journal_data, Bytes::from(seal))
-            .gas(self.config.base.max_gas)
-            .send()
-            .await?
-            .get_receipt()
-            .await?;
+        let journal = monitor_api::ttc_contract::decode_journal(&proof.journal, proof.batch_index)?;
+        let status = monitor_api::types::TradeStatus::try_from(journal.status)?;
+        info!(
+            "Trade outcome: {} ({} participants, preferences digest {:#})",
+            status, journal.participantCount, journal.preferencesDigest
+        );
+        // The seal commits to the journal bytes as the prover produced them
+        let journal_data = Bytes::from(proof.journal.clone());
+        let seal = Bytes::from(proof.seal.clone());
+        let (method, realloc_tx) = match proof.batch_index {
+            None => {
+                let tx = ttc
+                    .reallocateTokens(journal_data, seal)
+                    .gas(self.config.base.max_gas)
+                    .send()
+                    .await?
+                    .get_receipt()
+                    .await?;
+                ("reallocateTokens", tx)
+            }
+            Some(index) => {
+                let tx = ttc
+                    .reallocateTokensFromBatch(journal_data, U256::from(index), seal)
+                    .gas(self.config.base.max_gas)
+                    .send()
+                    .await?
+                    .get_receipt()
+                    .await?;
+                ("reallocateTokensFromBatch", tx)
+            }
+        };
         with_metrics(&self.gas_metrics, |m| {
-            m.inc_counter("reallocateTokens");
-            m.record_hist("reallocateTokens", realloc_tx.gas_used);
+            m.inc_counter(method);
+            m.record_hist(method, realloc_tx.gas_used);
         })
         .await;
         let stable: Vec<Actor> = self
             .actors
             .iter()
             .filter(|&a| {
-                !proof
+                !journal
                     .reallocations
                     .iter()
                     .any(|tr| tr.newOwner == a.address())
@@ -256,7 +287,7 @@ impl TestSetup {
             .iter()
             .cloned()
             .filter_map(|a| {
-                let tr = proof
+                let tr = journal
                     .reallocations
                     .iter()
                     .find(|tr| tr.newOwner == a.address())?;
@@ -380,7 +411,32 @@ impl TestSetup {
     }
 }
 
-async fn run_demo(setup: &TestSetup) -> Result<()> {
+// Measure the trade against the actors' preferences, by token hash
+fn allocation_stats(actors: &[Actor], trade_results: &TradeResults) -> AllocationStats {
+    let prefs = Preferences {
+        prefs: actors
+            .iter()
+            .map(|a| {
+                let prefs = a.preferences.iter().map(|t| t.hash()).collect();
+                (a.token.hash(), prefs)
+            })
+            .collect(),
+    };
+    let allocation = trade_results
+        .stable
+        .iter()
+        .map(|a| (a.token.hash(), a.token.hash()))
+        .chain(
+            trade_results
+                .traders
+                .iter()
+                .map(|(a, new_token_hash)| (a.token.hash(), *new_token_hash)),
+        )
+        .collect();
+    AllocationStats::new(&prefs, &Allocation { allocation })
+}
+
+async fn run_demo(setup: &TestSetup) -> Result<TradeResults> {
     let ttc = {
         let provider = create_provider(setup.node_url.clone(), setup.owner.clone());
         ITopTradingCycle::new(setup.ttc, provider)
@@ -406,7 +462,7 @@ async fn run_demo(setup: &TestSetup) -> Result<()> {
     }
     let trade_results = if starting_phase <= 2 {
         info!("Computing the reallocation");
-        let (proof, seal) = {
+        let proof = {
             sleep(tokio::time::Duration::from_secs(2));
             info!(
                 "Polling the monitor for proof status, timeout is {} seconds",
@@ -423,11 +479,10 @@ async fn run_demo(setup: &TestSetup) -> Result<()> {
                     monitor_api::rpc::MonitorApiClient::get_proof(&setup.monitor, *ttc.address())
                         .await?;
                 setup.checkpointer.save(Checkpoint::Proved(resp.clone()))?;
-                let journal = ITopTradingCycle::Journal::abi_decode(&resp.journal, true)?;
-                Ok((journal, resp.seal))
+                Ok(resp)
             }
         }?;
-        let res = setup.reallocate(proof.clone(), seal).await?;
+        let res = setup.reallocate(&proof).await?;
         setup.checkpointer.save(Checkpoint::Traded(res.clone()))?;
         res
     } else {
@@ -442,7 +497,7 @@ async fn run_demo(setup: &TestSetup) -> Result<()> {
     if starting_phase == 4 {
         info!("Contract is already closed, no further action needed");
     }
-    Ok(())
+    Ok(trade_results)
 }
 
 async fn submit_proof(setup: TestSetup) -> Result<()> {
@@ -456,14 +511,63 @@ async fn submit_proof(setup: TestSetup) -> Result<()> {
         anyhow::bail!("Contract is not in the Trade phase, cannot submit proof");
     }
     let proof = setup.checkpointer.load_proof()?;
-    let res = {
-        let journal = ITopTradingCycle::Journal::abi_decode(&proof.journal, true)?;
-        let seal = proof.seal;
-        setup.reallocate(journal, seal).await?
-    };
+    let res = setup.reallocate(&proof).await?;
     setup.checkpointer.save(Checkpoint::Traded(res.clone()))?;
     Ok(())
 }
+
+// Shorten a token hash to its first and last two bytes
+fn short_hash(hash: &B256) -> String {
+    let hex = format!("{:#}", hash);
+    format!("{}…{}", &hex[..6], &hex[hex.len() - 4..])
+}
+
+// Solve the pool's current preferences and render every round. The trace follows strict TTC,
+// so pools that run another mechanism, or where a depositor holds several tokens and trades
+// them as one participant, are refused rather than drawn with rounds they wouldn't run.
+async fn graph(config: GraphConfig) -> Result<()> {
+    let owner = PrivateKeySigner::from_str(config.base.owner_key.as_str())?;
+    let provider = create_provider(config.node_url()?, owner);
+    let ttc = ITopTradingCycle::new(config.ttc_address, provider);
+    let mechanism = ttc.mechanism().call().await?._0;
+    if mechanism != TOP_TRADING_CYCLE {
+        anyhow::bail!(
+            "Pool {:#} uses mechanism {}, but only top trading cycle pools can be graphed",
+            config.ttc_address,
+            mechanism
+        );
+    }
+    let token_preferences = ttc.getAllTokenPreferences().call().await?._0;
+    let depositors: HashSet<Address> = token_preferences.iter().map(|tp| tp.owner).collect();
+    if depositors.len() < token_preferences.len() {
+        anyhow::bail!(
+            "Pool {:#} has depositors with several tokens, which trade as one participant and \
+             can't be graphed",
+            config.ttc_address
+        );
+    }
+    let prefs = Preferences {
+        prefs: token_preferences
+            .into_iter()
+            .map(|tp| (tp.tokenHash, tp.preferences))
+            .collect(),
+    }
+    .normalize();
+    let trace = PreferenceGraph::new(prefs)?.solve_traced()?;
+    let rendered = match config.format {
+        GraphFormat::Dot => trace.dot(short_hash).to_string(),
+        GraphFormat::Mermaid => trace.mermaid(short_hash).to_string(),
+    };
+    match config.output {
+        Some(path) => {
+            std::fs::write(&path, rendered)?;
+            info!("Wrote {} rounds to {}", trace.rounds.len(), path);
+        }
+        None => print!("{}", rendered),
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     init_console_subscriber();
@@ -480,7 +584,16 @@ async fn main() -> Result<()> {
                     config.num_actors..=config.num_actors,
                 )))
                 .prop_map(|prefs| prefs.map(U256::from));
-                strategy.new_tree(&mut runner).unwrap().current()
+                let prefs = strategy
+                    .new_tree(&mut runner)
+                    .unwrap()
+                    .current()
+                    .normalize();
+                if let Err(errors) = prefs.validate() {
+                    let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                    anyhow::bail!("Invalid preferences: {}", errors.join(", "));
+                }
+                prefs
             };
             let setup = {
                 if let std::result::Result::Ok(actors) = checkpointer.load_assigned_tokens() {
@@ -496,8 +609,14 @@ async fn main() -> Result<()> {
                 }
             };
             let res = run_demo(&setup).await;
+            if let std::result::Result::Ok(trade_results) = &res {
+                info!(
+                    "Allocation: {:}",
+                    allocation_stats(&setup.actors, trade_results)
+                );
+            }
             info!("Metrics: {:}", &setup.gas_metrics.into_inner());
-            res
+            res.map(|_| ())
         }
         Command::SubmitProof(config) => {
             info!("{}", serde_json::to_string_pretty(&config).unwrap());
@@ -513,5 +632,6 @@ async fn main() -> Result<()> {
             };
             submit_proof(setup).await
         }
+        Command::Graph(config) => graph(config).await,
     }
 }
@@ -1,5 +1,5 @@
 use anyhow::{Ok, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use host::checkpoint::{Checkpoint, Checkpointer, ContractAddresses};
 use host::cli::BaseConfig;
 use host::contract::Artifacts;
@@ -10,9 +10,10 @@ use host::{
     },
     env::create_provider,
 };
+use monitor_api::types::Chain;
 use risc0_steel::alloy::{
     network::Ethereum,
-    primitives::U256,
+    primitives::{B256, U256},
     providers::Provider,
     signers::local::PrivateKeySigner,
     transports::http::{Client, Http},
@@ -32,6 +33,15 @@ pub mod contract {
     );
 }
 
+/// Allocation rule for the pool, in the order of `ITopTradingCycle.Mechanism`
+#[derive(Clone, Copy, Debug, Default, ValueEnum, Serialize)]
+pub enum Mechanism {
+    #[default]
+    TopTradingCycle,
+    SerialDictatorship,
+    RandomSerialDictatorship,
+}
+
 #[derive(Clone, Parser, Serialize)]
 pub struct DeployConfig {
     #[clap(flatten)]
@@ -45,6 +55,14 @@ pub struct DeployConfig {
 
     #[arg(long, env = "PHASE_DURATION", default_value_t = 0)]
     pub phase_duration: u64,
+
+    #[arg(long, env = "MECHANISM", value_enum, default_value_t = Mechanism::TopTradingCycle)]
+    pub mechanism: Mechanism,
+
+    /// Hard fork schedule the prover runs the chain with, which together with `--chain-id`
+    /// fixes the chain spec the contract accepts proofs for
+    #[arg(long, env = "CHAIN", value_enum)]
+    pub chain: Chain,
 }
 
 impl DeployConfig {
@@ -56,6 +74,8 @@ impl DeployConfig {
 pub async fn deploy_for_test(
     num_erc721: usize,
     phase_duration: u64,
+    mechanism: Mechanism,
+    config_id: B256,
     provider: impl Provider<Http<Client>, Ethereum> + Clone,
     dev_mode: bool,
 ) -> Result<Artifacts> {
@@ -80,9 +100,16 @@ pub async fn deploy_for_test(
             *Verifier::deploy(&provider).await?.address()
         };
         let duration = U256::from(phase_duration);
-        *contract::TopTradingCycle::deploy(&provider, verifier, duration)
-            .await?
-            .address()
+        info!("Using mechanism {:?}", mechanism);
+        *contract::TopTradingCycle::deploy(
+            &provider,
+            verifier,
+            duration,
+            mechanism as u8,
+            config_id,
+        )
+        .await?
+        .address()
     };
 
     Ok(Artifacts { ttc, nft })
@@ -97,6 +124,8 @@ async fn deploy_contracts(config: DeployConfig) -> Result<ContractAddresses> {
     let Artifacts { ttc, nft } = deploy_for_test(
         config.num_erc721,
         config.phase_duration,
+        config.mechanism,
+        config.chain.config_id(Some(config.base.chain_id)),
         provider.clone(),
         config.mock_verifier,
     )
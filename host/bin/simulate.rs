@@ -0,0 +1,149 @@
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
+use proptest::{
+    arbitrary::Arbitrary,
+    strategy::{BoxedStrategy, Strategy, ValueTree},
+    test_runner::{Config, RngAlgorithm, TestRng, TestRunner},
+};
+use std::collections::BTreeMap;
+use ttc::{
+    generators,
+    mechanism::{
+        BoundedExchange, Mechanism, RandomSerialDictatorship, SerialDictatorship, TopTradingCycle,
+    },
+    stats::AllocationStats,
+    strict::{Preferences, TTCError},
+};
+
+type BoxedMechanism = Box<dyn Mechanism<u32, Error = TTCError>>;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Profile {
+    Uniform,
+    Popularity,
+    Mallows,
+    Correlated,
+}
+
+/// Run many random pools through every mechanism and report how they compare
+#[derive(Clone, Parser)]
+struct SimulateConfig {
+    #[arg(long, value_enum, default_value_t = Profile::Popularity)]
+    profile: Profile,
+
+    #[arg(long, default_value_t = 100)]
+    pools: usize,
+
+    #[arg(long, default_value_t = 20)]
+    participants: usize,
+
+    /// Zipf exponent for popularity, dispersion in [0, 1] for Mallows, or affinity for
+    /// correlated profiles
+    #[arg(long, default_value_t = 1.0)]
+    param: f64,
+
+    /// Number of collections for correlated profiles
+    #[arg(long, default_value_t = 4)]
+    collections: usize,
+
+    /// Cut every preference list to a random length between 1 and this. Bounded exchange is
+    /// exponential in the worst case, so long lists make it slow.
+    #[arg(long, default_value_t = 5)]
+    max_list_len: usize,
+
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+// Totals over all pools for one mechanism
+#[derive(Default)]
+struct Summary {
+    participants: usize,
+    traded: usize,
+    first_choices: usize,
+    rank_improvement: f64,
+    cycle_lengths: BTreeMap<usize, usize>,
+}
+
+impl Summary {
+    fn add(&mut self, stats: &AllocationStats) {
+        self.participants += stats.participants;
+        self.traded += stats.traded;
+        self.first_choices += stats.received_ranks.get(&0).unwrap_or(&0);
+        self.rank_improvement += stats.mean_rank_improvement * stats.participants as f64;
+        for (len, count) in &stats.cycle_lengths {
+            *self.cycle_lengths.entry(*len).or_default() += count;
+        }
+    }
+}
+
+fn strategy(config: &SimulateConfig) -> BoxedStrategy<Preferences<u32>> {
+    let n = config.participants;
+    let profile = match config.profile {
+        Profile::Uniform => Preferences::<u32>::arbitrary_with(Some(n..=n)),
+        Profile::Popularity => generators::popularity(n, config.param),
+        Profile::Mallows => generators::mallows(n, config.param),
+        Profile::Correlated => generators::correlated(n, config.collections, config.param),
+    };
+    generators::truncated(profile, 1..=config.max_list_len.max(1))
+}
+
+fn main() -> Result<()> {
+    let config = SimulateConfig::parse();
+    let mut runner = {
+        let mut seed = [0; 32];
+        seed[..8].copy_from_slice(&config.seed.to_le_bytes());
+        TestRunner::new_with_rng(
+            Config::default(),
+            TestRng::from_seed(RngAlgorithm::ChaCha, &seed),
+        )
+    };
+    let strategy = strategy(&config);
+    let mechanisms: Vec<(&str, BoxedMechanism)> = vec![
+        ("ttc", Box::new(TopTradingCycle)),
+        ("sd", Box::new(SerialDictatorship::default())),
+        (
+            "rsd",
+            Box::new(RandomSerialDictatorship { seed: config.seed }),
+        ),
+        ("bounded-2", Box::new(BoundedExchange { max_len: 2 })),
+        ("bounded-3", Box::new(BoundedExchange { max_len: 3 })),
+    ];
+    let mut summaries: Vec<Summary> = mechanisms.iter().map(|_| Summary::default()).collect();
+    for _ in 0..config.pools {
+        let prefs = strategy
+            .new_tree(&mut runner)
+            .map_err(|e| anyhow::anyhow!("Failed to generate preferences: {}", e))?
+            .current();
+        for ((_, mechanism), summary) in mechanisms.iter().zip(summaries.iter_mut()) {
+            let alloc = mechanism.allocate(&prefs)?;
+            summary.add(&AllocationStats::new(&prefs, &alloc));
+        }
+    }
+
+    println!(
+        "{} pools of {} participants, {:?} profile",
+        config.pools, config.participants, config.profile
+    );
+    println!(
+        "{:<10} {:>10} {:>14} {:>16}  cycle lengths",
+        "mechanism", "traded", "first choice", "rank improvement"
+    );
+    for ((name, _), summary) in mechanisms.iter().zip(summaries) {
+        let total = summary.participants.max(1) as f64;
+        let cycle_lengths: Vec<String> = summary
+            .cycle_lengths
+            .iter()
+            .map(|(len, count)| format!("{}: {}", len, count))
+            .collect();
+        println!(
+            "{:<10} {:>9.1}% {:>13.1}% {:>16.2}  {}",
+            name,
+            100.0 * summary.traded as f64 / total,
+            100.0 * summary.first_choices as f64 / total,
+            summary.rank_improvement / total,
+            cycle_lengths.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use risc0_steel::alloy::primitives::Address;
 use serde::Serialize;
 use url::Url;
@@ -8,6 +8,9 @@ use url::Url;
 pub enum Command {
     E2E(DemoConfig),
     SubmitProof(DemoConfig),
+    /// Render the rounds of the solver for a deployed top trading cycle pool's current
+    /// preferences
+    Graph(GraphConfig),
 }
 
 #[derive(Clone, Parser, Serialize)]
@@ -87,3 +90,31 @@ impl DemoConfig {
         Url::parse(&monitor_url)
     }
 }
+
+#[derive(Clone, Copy, ValueEnum, Serialize)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+#[derive(Clone, Parser, Serialize)]
+pub struct GraphConfig {
+    #[clap(flatten)]
+    pub base: BaseConfig,
+
+    #[arg(long, env = "TTC_ADDRESS")]
+    pub ttc_address: Address,
+
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    pub format: GraphFormat,
+
+    /// Write the graph to this file instead of stdout
+    #[arg(long)]
+    pub output: Option<String>,
+}
+
+impl GraphConfig {
+    pub fn node_url(&self) -> Result<Url, url::ParseError> {
+        self.base.node_url()
+    }
+}
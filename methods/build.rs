@@ -24,17 +24,28 @@ fn main() {
     // Builds can be made deterministic, and thereby reproducible, by using Docker to build the
     // guest. Check the RISC0_USE_DOCKER variable and use Docker to build the guest if set.
     println!("cargo:rerun-if-env-changed=RISC0_USE_DOCKER");
+    println!("cargo:rerun-if-env-changed=GUEST_FEATURES");
     println!("cargo:rerun-if-changed=build.rs");
     let manifest_dir = PathBuf::from(std::env::var_os("CARGO_MANIFEST_DIR").unwrap());
     let use_docker = env::var("RISC0_USE_DOCKER").ok().map(|_| DockerOptions {
         root_dir: Some(manifest_dir.join("..")),
     });
 
+    // Comma separated guest features, e.g. GUEST_FEATURES=ttc-std to compare cycle counts
+    let features = env::var("GUEST_FEATURES")
+        .map(|f| {
+            f.split(',')
+                .filter(|f| !f.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+
     // Generate Rust source files for the methods crate.
     let guests: Vec<risc0_build::GuestListEntry> = embed_methods_with_options(HashMap::from([(
         "ttc-guests",
         GuestOptions {
-            features: Vec::new(),
+            features,
             use_docker,
         },
     )]));
@@ -15,15 +15,20 @@
 #![allow(unused_doc_comments)]
 #![no_main]
 
-use alloy_primitives::{Address, B256};
+use alloy_primitives::{keccak256, Address, B256, U256};
 use alloy_sol_types::{SolValue, sol};
 use risc0_steel::{
-    ethereum::{EthEvmInput, ETH_SEPOLIA_CHAIN_SPEC},
+    ethereum::{EthChainSpec, EthEvmInput},
     Contract,
 };
 use risc0_zkvm::guest::env;
-use hashbrown::HashMap;
-use ttc::strict::{self, Preferences};
+use hashbrown::{HashMap, HashSet};
+use std::collections::BTreeMap;
+use ttc::{
+    mechanism::{Mechanism, RandomSerialDictatorship, SerialDictatorship},
+    multi,
+    strict::{self, Preferences},
+};
 
 risc0_zkvm::guest::entry!(main);
 
@@ -34,6 +39,16 @@ sol!(
 );
 
 
+// Must match the order of `ITopTradingCycle.Mechanism`
+const TOP_TRADING_CYCLE: u8 = 0;
+const SERIAL_DICTATORSHIP: u8 = 1;
+const RANDOM_SERIAL_DICTATORSHIP: u8 = 2;
+
+// Must match the order of `ITopTradingCycle.TradeStatus`
+const SUCCESS: u8 = 0;
+const EMPTY_POOL: u8 = 1;
+const INVALID_PREFERENCES: u8 = 2;
+const UNKNOWN_MECHANISM: u8 = 3;
 
 fn build_owner_dict(prefs: &[ITopTradingCycle::TokenPreferences]) -> HashMap<B256, Address> {
     prefs
@@ -43,12 +58,36 @@ fn build_owner_dict(prefs: &[ITopTradingCycle::TokenPreferences]) -> HashMap<B25
         .collect()
 }
 
+fn is_multi_unit(depositor_address_from_token_hash: &HashMap<B256, Address>) -> bool {
+    let mut seen = HashSet::new();
+    depositor_address_from_token_hash
+        .values()
+        .any(|owner| !seen.insert(owner))
+}
+
+// Log why the pool can't be solved and give up on it with the given status
+fn reject<E: std::fmt::Display>(status: u8) -> impl FnOnce(E) -> u8 {
+    move |e| {
+        eprintln!("Leaving every token with its depositor: {}", e);
+        status
+    }
+}
+
 // This function calls the solver and produces the data we need to
-// submit to the contract
+// submit to the contract. A pool that can't be solved fails with the status to commit
+// instead, so that the contract can still settle.
 fn reallocate(
+    mechanism: u8,
+    seed: B256,
     depositor_address_from_token_hash: &HashMap<B256, Address>,
     prefs: Vec<ITopTradingCycle::TokenPreferences>,
-) -> Vec<ITopTradingCycle::TokenReallocation> {
+) -> Result<Vec<ITopTradingCycle::TokenReallocation>, u8> {
+    if prefs.is_empty() {
+        eprintln!("Nothing was deposited");
+        return Err(EMPTY_POOL);
+    }
+    // The contract returns tokens in deposit order, which serial dictatorship uses as priority
+    let deposit_order: Vec<B256> = prefs.iter().map(|tp| tp.tokenHash).collect();
     let prefs = {
         let ps = prefs
             .into_iter()
@@ -60,22 +99,76 @@ fn reallocate(
                  }| { (tokenHash, preferences) },
             )
             .collect();
-        Preferences::new(ps).unwrap()
+        let prefs = Preferences { prefs: ps }.normalize();
+        if let Err(errors) = prefs.validate() {
+            let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+            eprintln!("Invalid preferences: {}", errors.join(", "));
+            return Err(INVALID_PREFERENCES);
+        }
+        prefs
+    };
+    // Collect into a sorted map so that the same preferences always produce the same journal
+    let allocation: BTreeMap<B256, B256> = match mechanism {
+        TOP_TRADING_CYCLE => {
+            let cycles = if is_multi_unit(depositor_address_from_token_hash) {
+                // Some depositors own several tokens, so trade on behalf of the depositor rather
+                // than treating each token as its own participant
+                let owners = depositor_address_from_token_hash
+                    .iter()
+                    .map(|(token_hash, owner)| (*token_hash, *owner))
+                    .collect();
+                let prefs = multi::Preferences::from_token_preferences(owners, prefs)
+                    .map_err(reject(INVALID_PREFERENCES))?;
+                multi::PreferenceGraph::new(prefs)
+                    .and_then(|g| g.solve_preferences())
+                    .map_err(reject(INVALID_PREFERENCES))?
+            } else {
+                strict::PreferenceGraph::new(prefs)
+                    .and_then(|mut g| g.solve_preferences())
+                    .map_err(reject(INVALID_PREFERENCES))?
+            };
+            strict::CanonicalAllocation::from(strict::canonical_cycles(cycles)).allocation
+        }
+        // Serial dictatorship treats every token as its own participant, so a depositor with
+        // several tokens chooses once per token
+        SERIAL_DICTATORSHIP => {
+            let sd = SerialDictatorship {
+                priority: deposit_order,
+            };
+            sd.allocate(&prefs)
+                .map_err(reject(INVALID_PREFERENCES))?
+                .allocation
+                .into_iter()
+                .collect()
+        }
+        RANDOM_SERIAL_DICTATORSHIP => {
+            // Hash all 256 bits of the block hash down to the 64 the shuffle takes
+            let digest = keccak256(seed);
+            let rsd = RandomSerialDictatorship {
+                seed: u64::from_be_bytes(digest[..8].try_into().unwrap()),
+            };
+            rsd.allocate(&prefs)
+                .map_err(reject(INVALID_PREFERENCES))?
+                .allocation
+                .into_iter()
+                .collect()
+        }
+        _ => {
+            eprintln!("Unknown mechanism {}", mechanism);
+            return Err(UNKNOWN_MECHANISM);
+        }
     };
-    let mut g = strict::PreferenceGraph::new(prefs).unwrap();
-    let alloc = strict::Allocation::from(g.solve_preferences().unwrap());
-    alloc
-        .allocation
+    // Every token in the allocation came from the contract's list, so it has a depositor
+    allocation
         .into_iter()
         .map(|(new_owner, token_hash)| {
-            let new_owner = depositor_address_from_token_hash
+            let new_owner = *depositor_address_from_token_hash
                 .get(&new_owner)
-                .unwrap()
-                .clone();
-            ITopTradingCycle::TokenReallocation {
+                .ok_or(INVALID_PREFERENCES)?;
+            Ok(ITopTradingCycle::TokenReallocation {
                 newOwner: new_owner,
                 tokenHash: token_hash,
-            }
+            })
         })
         .collect()
 }
@@ -87,40 +180,87 @@ sol! {
     struct Journal {
         Commitment commitment;
         address ttcContract;
+        uint256 chainId;
+        uint8 mechanism;
+        uint8 status;
+        bytes32 preferencesDigest;
+        uint256 participantCount;
         ITopTradingCycle.TokenReallocation[] reallocations;
     }
-}
 
+    struct BatchJournal {
+        Journal[] journals;
+    }
+}
 
-fn main() {
-    // Read the input from the guest environment.
-    eprintln!("Reading input");
-    let input: EthEvmInput = env::read();
-    let ttc: Address = env::read();
-
+// Solve one pool against the state in its input
+fn solve_pool(chain_spec: &EthChainSpec, input: EthEvmInput, ttc: Address) -> Journal {
     // Converts the input into a `EvmEnv` for execution. The `with_chain_spec` method is used
     // to specify the chain configuration. It checks that the state matches the state root in the
     // header provided in the input.
-    let env = input.into_env().with_chain_spec(&ETH_SEPOLIA_CHAIN_SPEC);
+    let env = input.into_env().with_chain_spec(chain_spec);
 
     // Execute the view call; it returns the result in the type generated by the `sol!` macro.
     let call = TopTradingCycle::getAllTokenPreferencesCall{};
-    let preferences = Contract::new(ttc, &env).call_builder(&call).call()._0;
+    let contract = Contract::new(ttc, &env);
+    let preferences = contract.call_builder(&call).call()._0;
+    let mechanism = contract
+        .call_builder(&TopTradingCycle::mechanismCall {})
+        .call()
+        ._0;
+    // The hash of the block that moved the pool to Trade, which the commitment binds and
+    // nobody could know while ranking was open
+    let seed = env.commitment().digest;
     let initial_owners = build_owner_dict(&preferences);
+    // Identifies the exact snapshot solved over, the same way `abi.encode` would in Solidity
+    let preferences_digest = keccak256(preferences.abi_encode());
+    // Depositors rather than tokens, since in multi-unit pools one depositor owns several
+    let participant_count = U256::from(initial_owners.values().collect::<HashSet<_>>().len());
+    // When the pool can't be solved everyone keeps their own token, which needs no reallocations
+    let (status, res) = match reallocate(mechanism, seed, &initial_owners, preferences) {
+        Ok(res) => (SUCCESS, res),
+        Err(status) => (status, vec![]),
+    };
     let reallocations = {
-        let res : Vec<ITopTradingCycle::TokenReallocation> = reallocate(&initial_owners, preferences);
         res.into_iter().filter(|ITopTradingCycle::TokenReallocation { newOwner, tokenHash }| {
-            newOwner != initial_owners.get(tokenHash).unwrap()
+            initial_owners.get(tokenHash) != Some(newOwner)
         }).collect()
     };
 
-    // Commit the block hash and number used when deriving `view_call_env` to the journal.
-    let journal = Journal {
+    // Commit the block hash and number used when deriving `view_call_env` to the journal. The
+    // commitment's `configID` is the digest of the chain spec, which the contract checks
+    // against the one it was deployed for.
+    Journal {
         commitment: env.into_commitment(),
         ttcContract: ttc,
+        chainId: U256::from(chain_spec.chain_id),
+        mechanism,
+        status,
+        preferencesDigest: preferences_digest,
+        participantCount: participant_count,
         reallocations,
-    };
+    }
+}
 
+fn main() {
+    // Read the input from the guest environment: the spec of the chain the pools are on, which
+    // ends up in the journal for the contracts to check, and every pool in the batch, with the
+    // state to solve it against
+    eprintln!("Reading input");
+    let chain_spec: EthChainSpec = env::read();
+    let pools: Vec<(EthEvmInput, Address)> = env::read();
+
+    let mut journals: Vec<Journal> = pools
+        .into_iter()
+        .map(|(input, ttc)| solve_pool(&chain_spec, input, ttc))
+        .collect();
+
+    // A single pool commits its journal as is, for `reallocateTokens`. A batch commits every
+    // journal, and each contract picks its own with `reallocateTokensFromBatch`.
     eprintln!("Committing the result");
-    env::commit_slice(&journal.abi_encode());
-}
\ No newline at end of file
+    if journals.len() == 1 {
+        env::commit_slice(&journals.remove(0).abi_encode());
+    } else {
+        env::commit_slice(&BatchJournal { journals }.abi_encode());
+    }
+}
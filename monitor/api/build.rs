@@ -1,4 +1,4 @@
-// server/build.rs
+// api/build.rs
 use std::env;
 use std::fs;
 use std::path::Path;
@@ -1,2 +1,3 @@
 pub mod rpc;
+pub mod ttc_contract;
 pub mod types;
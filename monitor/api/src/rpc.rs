@@ -1,4 +1,4 @@
-use super::types::{Proof, ProofStatus};
+use super::types::{Proof, ProofStatus, TradeOutcome};
 use jsonrpsee::{proc_macros::rpc, types::ErrorObjectOwned};
 use risc0_steel::alloy::primitives::Address;
 
@@ -13,6 +13,10 @@ pub trait MonitorApi {
     #[method(name = "getProofStatus")]
     async fn get_proof_status(&self, address: Address) -> Result<ProofStatus, ErrorObjectOwned>;
 
+    /// The outcome committed in the contract's proof, once the proof is ready
+    #[method(name = "getTradeOutcome")]
+    async fn get_trade_outcome(&self, address: Address) -> Result<TradeOutcome, ErrorObjectOwned>;
+
     #[method(name = "getImageIDContract")]
     async fn get_image_id_contract(&self) -> Result<String, ErrorObjectOwned>;
 
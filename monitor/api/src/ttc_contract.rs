@@ -0,0 +1,57 @@
+use risc0_steel::alloy::{sol, sol_types::SolValue};
+
+sol!(
+    #[sol(rpc, all_derives)]
+    ITopTradingCycle,
+    "../../contract/out/ITopTradingCycle.sol/ITopTradingCycle.json"
+);
+
+// Decode a pool's journal from a proof, picking it out of the batch if the proof covers several
+// pools
+pub fn decode_journal(
+    journal: &[u8],
+    batch_index: Option<u32>,
+) -> anyhow::Result<ITopTradingCycle::Journal> {
+    match batch_index {
+        None => Ok(ITopTradingCycle::Journal::abi_decode(journal, true)?),
+        Some(index) => ITopTradingCycle::BatchJournal::abi_decode(journal, true)?
+            .journals
+            .into_iter()
+            .nth(index as usize)
+            .ok_or_else(|| anyhow::anyhow!("Batch index {} is out of bounds", index)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use risc0_steel::alloy::primitives::{Address, U256};
+
+    fn journal(pool: u8) -> ITopTradingCycle::Journal {
+        ITopTradingCycle::Journal {
+            ttcContract: Address::repeat_byte(pool),
+            chainId: U256::from(31337),
+            participantCount: U256::from(pool),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decode_journal_test() {
+        let single = journal(1).abi_encode();
+        assert_eq!(decode_journal(&single, None).unwrap(), journal(1));
+
+        let batch = ITopTradingCycle::BatchJournal {
+            journals: vec![journal(1), journal(2)],
+        }
+        .abi_encode();
+        assert_eq!(decode_journal(&batch, Some(0)).unwrap(), journal(1));
+        assert_eq!(decode_journal(&batch, Some(1)).unwrap(), journal(2));
+        assert!(decode_journal(&batch, Some(2))
+            .unwrap_err()
+            .to_string()
+            .contains("out of bounds"));
+        // A batch isn't a single journal
+        assert!(decode_journal(&single, Some(0)).is_err());
+    }
+}
@@ -1,9 +1,20 @@
+use clap::ValueEnum;
+use risc0_steel::{
+    alloy::primitives::B256,
+    config::ChainSpec,
+    ethereum::{EthChainSpec, ETH_MAINNET_CHAIN_SPEC, ETH_SEPOLIA_CHAIN_SPEC},
+};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Proof {
     pub journal: Vec<u8>,
     pub seal: Vec<u8>,
+    /// Set when the proof covers a batch of pools: `journal` is then the whole batch, and this
+    /// is the position of the contract's own journal in it, for `reallocateTokensFromBatch`
+    #[serde(default)]
+    pub batch_index: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -13,3 +24,83 @@ pub enum ProofStatus {
     Completed,
     Errored(String),
 }
+
+/// What the guest did with the pool. Unless the trade succeeded, every token stays with its
+/// depositor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TradeStatus {
+    Success,
+    EmptyPool,
+    InvalidPreferences,
+    UnknownMechanism,
+}
+
+// Decodes the journal's `status`, which must match the order of `ITopTradingCycle.TradeStatus`
+impl TryFrom<u8> for TradeStatus {
+    type Error = anyhow::Error;
+
+    fn try_from(status: u8) -> Result<Self, Self::Error> {
+        match status {
+            0 => Ok(TradeStatus::Success),
+            1 => Ok(TradeStatus::EmptyPool),
+            2 => Ok(TradeStatus::InvalidPreferences),
+            3 => Ok(TradeStatus::UnknownMechanism),
+            _ => Err(anyhow::anyhow!("Unknown trade status {}", status)),
+        }
+    }
+}
+
+impl fmt::Display for TradeStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TradeStatus::Success => write!(f, "tokens were reallocated"),
+            TradeStatus::EmptyPool => write!(f, "nothing was deposited"),
+            TradeStatus::InvalidPreferences => write!(
+                f,
+                "the preferences couldn't be solved, so every token stays with its depositor"
+            ),
+            TradeStatus::UnknownMechanism => write!(
+                f,
+                "the guest doesn't know the pool's mechanism, so every token stays with its depositor"
+            ),
+        }
+    }
+}
+
+/// What the proof commits to about the trade, decoded from its journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TradeOutcome {
+    pub status: TradeStatus,
+    /// keccak256 of the ABI encoded `TokenPreferences[]` the guest solved over
+    pub preferences_digest: B256,
+    /// How many depositors were in the pool. A depositor with several tokens counts once.
+    pub participant_count: u64,
+}
+
+/// The hard fork schedules the prover knows. A contract only accepts proofs made with the
+/// spec it was deployed for, see [`Chain::config_id`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+pub enum Chain {
+    Mainnet,
+    Sepolia,
+}
+
+impl Chain {
+    /// The chain's spec, or the spec of a chain with its hard forks but another ID, such as a
+    /// local anvil node
+    pub fn spec(self, chain_id: Option<u64>) -> EthChainSpec {
+        let spec = match self {
+            Chain::Mainnet => (*ETH_MAINNET_CHAIN_SPEC).clone(),
+            Chain::Sepolia => (*ETH_SEPOLIA_CHAIN_SPEC).clone(),
+        };
+        match chain_id {
+            Some(chain_id) => ChainSpec { chain_id, ..spec },
+            None => spec,
+        }
+    }
+
+    /// Digest of [`Chain::spec`], which proofs commit to as their Steel commitment's `configID`
+    pub fn config_id(self, chain_id: Option<u64>) -> B256 {
+        B256::from_slice(self.spec(chain_id).digest().as_bytes())
+    }
+}
@@ -54,12 +54,21 @@ async fn create_schema(pool: &PgPool) -> Result<(), sqlx::Error> {
         CREATE TABLE IF NOT EXISTS proofs (
             address BYTEA PRIMARY KEY,
             proof BYTEA NOT NULL,
-            seal BYTEA NOT NULL
+            seal BYTEA NOT NULL,
+            batch_index INTEGER
         )
     "#,
     ))
     .await?;
 
+    // Proofs tables created before batch proving don't have the column yet
+    pool.execute(sqlx::query(
+        r#"
+        ALTER TABLE proofs ADD COLUMN IF NOT EXISTS batch_index INTEGER
+    "#,
+    ))
+    .await?;
+
     // Create trigger function for notifications
     pool.execute(sqlx::query(
         r#"
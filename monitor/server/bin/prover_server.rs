@@ -4,14 +4,15 @@ use jsonrpsee::{
     server::Server,
     types::{ErrorObject, ErrorObjectOwned},
 };
+use monitor_api::ttc_contract;
 use monitor_server::{
     app_config::init_console_subscriber,
     db::{self, schema::JobStatus},
     prover::{
         rpc::ProverApiServer,
-        types::{Proof, ProverT},
+        types::{Estimate, Proof, ProverT},
     },
-    ttc_contract, utils,
+    utils,
 };
 use risc0_steel::alloy::primitives::Address;
 use sqlx::types::chrono;
@@ -21,12 +22,16 @@ use tracing::{debug, error, info};
 mod app_env {
     use anyhow::Result;
     use clap::Parser;
+    use monitor_api::types::Chain;
     use monitor_server::{
         app_config,
         db::DB,
         prover::{db::Database, local::Prover},
     };
+    use risc0_steel::alloy::primitives::Address;
     use serde::Serialize;
+    use std::{collections::HashSet, sync::Arc, time::Duration};
+    use tokio::sync::Mutex;
     use url::Url;
 
     #[derive(Parser, Serialize)]
@@ -40,6 +45,30 @@ mod app_env {
 
         #[arg(long, env = "IMAGE_ID_CONTRACT")]
         pub image_id_contract: String,
+
+        /// Seconds to wait for more pools after one asks for an async proof, so that pools
+        /// reaching the Trade phase around the same time share one proof. 0 proves every pool
+        /// on its own.
+        #[arg(long, env = "BATCH_WINDOW", default_value = "0")]
+        pub batch_window: u64,
+
+        /// Hard fork schedule of the chain the node is on. The TTC contracts must be deployed
+        /// for the same schedule and chain ID, or they reject the proofs.
+        #[arg(long, env = "CHAIN", value_enum)]
+        pub chain: Chain,
+
+        /// Chain ID of a local or private chain that follows the `--chain` hard forks, such as
+        /// an anvil node
+        #[arg(long, env = "CHAIN_ID")]
+        pub chain_id: Option<u64>,
+    }
+
+    #[derive(Default)]
+    pub struct Batches {
+        // Pools waiting for the current batch window to close
+        pub pending: Vec<Address>,
+        // Pools whose proof is being made, which mustn't be queued again until it's done
+        pub proving: HashSet<Address>,
     }
 
     #[derive(Clone)]
@@ -48,6 +77,8 @@ mod app_env {
         pub prover: Prover,
         pub node_url: Url,
         pub image_id_contract: String,
+        pub batch_window: Duration,
+        pub batches: Arc<Mutex<Batches>>,
     }
 
     impl AppEnv {
@@ -58,13 +89,15 @@ mod app_env {
             }?
             .await;
             let node_url = app_config.base_config.node_url()?;
-            let prover = Prover::new(&node_url);
+            let prover = Prover::new(&node_url, app_config.chain.spec(app_config.chain_id));
             let image_id_contract = std::fs::read_to_string(app_config.image_id_contract)?;
             Ok(Self {
                 db,
                 prover,
                 node_url,
                 image_id_contract,
+                batch_window: Duration::from_secs(app_config.batch_window),
+                batches: Arc::new(Mutex::new(Batches::default())),
             })
         }
     }
@@ -105,48 +138,107 @@ impl ProverApiImpl {
 
     async fn prove_impl(&self, address: Address) -> anyhow::Result<Proof> {
         info!("Starting prover for TTC contract at address: {:#}", address);
-        let proof = self.app_env.prover.prove(address).await;
-        match proof {
+        self.prove_batch_impl(&[address]).await
+    }
+
+    // Prove the pools together, then store the proof for every pool and mark its job
+    // completed. A pool that can't be preflighted is marked errored and left out of the batch,
+    // and if proving or storing the proofs fails every remaining job is marked errored.
+    async fn prove_batch_impl(&self, addresses: &[Address]) -> anyhow::Result<Proof> {
+        let mut pools = Vec::with_capacity(addresses.len());
+        let mut preflight_err = None;
+        for &address in addresses {
+            match self.app_env.prover.evm_input(address).await {
+                Ok(input) => pools.push((input, address)),
+                Err(err) => {
+                    error!("Preflight for {:#} errored with message {}", address, err);
+                    self.fail_jobs(&[address], &err).await;
+                    preflight_err = Some(err);
+                }
+            }
+        }
+        if pools.is_empty() {
+            return Err(preflight_err.unwrap_or_else(|| anyhow::anyhow!("No pools to prove")));
+        }
+
+        // The journal lists the pools that made it into the batch, in this order
+        let addresses: Vec<Address> = pools.iter().map(|(_, address)| *address).collect();
+        match self.app_env.prover.prove_inputs(pools).await {
             Ok(proof) => {
                 info!("Prover successful, writing to DB");
-                self.app_env
-                    .db
-                    .create_proof(&db::schema::Proof {
+                // A single pool's journal isn't wrapped in a batch
+                let batched = addresses.len() > 1;
+                let proofs: Vec<db::schema::Proof> = addresses
+                    .iter()
+                    .enumerate()
+                    .map(|(index, address)| db::schema::Proof {
                         address: address.as_slice().to_vec(),
                         proof: proof.journal.clone(),
                         seal: proof.seal.clone(),
+                        batch_index: batched.then_some(index as i32),
                     })
-                    .await?;
-                let now = chrono::Utc::now();
-                self.app_env
+                    .collect();
+                if let Err(err) = self
+                    .app_env
                     .db
-                    .update_job_status(address.as_slice(), JobStatus::Completed, None, Some(now))
-                    .await?;
+                    .complete_jobs(&proofs, chrono::Utc::now())
+                    .await
+                {
+                    let err = anyhow::Error::from(err);
+                    error!("Storing the proofs errored with message {}", err);
+                    self.fail_jobs(&addresses, &err).await;
+                    return Err(err);
+                }
                 Ok(proof)
             }
             Err(err) => {
-                let err_str = err.to_string();
-                error!("Prover errored with message {}", err_str);
-                let now = chrono::Utc::now();
-                self.app_env
-                    .db
-                    .update_job_status(
-                        address.as_slice(),
-                        JobStatus::Errored,
-                        Some(err_str),
-                        Some(now),
-                    )
-                    .await?;
+                error!("Prover errored with message {}", err);
+                self.fail_jobs(&addresses, &err).await;
                 Err(err)
             }
         }
     }
+
+    // Mark the jobs errored. A job that can't be updated is logged and skipped, so that one
+    // DB error doesn't leave the rest of the batch marked as running.
+    async fn fail_jobs(&self, addresses: &[Address], err: &anyhow::Error) {
+        let now = chrono::Utc::now();
+        for address in addresses {
+            if let Err(db_err) = self
+                .app_env
+                .db
+                .update_job_status(
+                    address.as_slice(),
+                    JobStatus::Errored,
+                    Some(err.to_string()),
+                    Some(now),
+                )
+                .await
+            {
+                error!(
+                    "Failed to mark the job for {:#} errored: {}",
+                    address, db_err
+                );
+            }
+        }
+    }
 }
 
 #[async_trait]
 impl ProverApiServer for ProverApiImpl {
     async fn prove(&self, address: Address) -> Result<Proof, ErrorObjectOwned> {
+        {
+            // Proving a pool that's already queued or being proved would store its proof twice
+            let mut batches = self.app_env.batches.lock().await;
+            if batches.pending.contains(&address) || batches.proving.contains(&address) {
+                let err_str = format!("TTC contract {:#} is already being proved", address);
+                error!(err_str);
+                return Err(ErrorObject::owned(-32001, err_str, None::<()>));
+            }
+            batches.proving.insert(address);
+        }
         let res = self.prove_impl(address).await;
+        self.app_env.batches.lock().await.proving.remove(&address);
         match res {
             Ok(proof) => Ok(proof),
             Err(err) => Err(ErrorObject::owned(-32001, err.to_string(), None::<()>)),
@@ -155,9 +247,49 @@ impl ProverApiServer for ProverApiImpl {
 
     async fn prove_async(&self, address: Address) -> Result<(), ErrorObjectOwned> {
         self.assert_in_trade_phase(address).await?;
+        let batch_window = self.app_env.batch_window;
+        {
+            // A pool that's already queued or being proved is left to that proof. Otherwise the
+            // first pool to arrive opens a batch, and the pools arriving before the window
+            // closes join it. Pools arriving while a batch is being proved open the next one.
+            let mut batches = self.app_env.batches.lock().await;
+            if batches.pending.contains(&address) || batches.proving.contains(&address) {
+                return Ok(());
+            }
+            if batch_window.is_zero() {
+                batches.proving.insert(address);
+            } else {
+                batches.pending.push(address);
+                if batches.pending.len() > 1 {
+                    return Ok(());
+                }
+            }
+        }
         let api = self.clone();
         tokio::spawn(async move {
-            let res = api.prove_impl(address).await;
+            let (addresses, res) = if batch_window.is_zero() {
+                (vec![address], api.prove_impl(address).await)
+            } else {
+                tokio::time::sleep(batch_window).await;
+                let addresses = {
+                    let mut batches = api.app_env.batches.lock().await;
+                    let addresses = std::mem::take(&mut batches.pending);
+                    batches.proving.extend(addresses.iter().copied());
+                    addresses
+                };
+                info!(
+                    "Starting prover for a batch of {} TTC contracts",
+                    addresses.len()
+                );
+                let res = api.prove_batch_impl(&addresses).await;
+                (addresses, res)
+            };
+            {
+                let mut batches = api.app_env.batches.lock().await;
+                for address in &addresses {
+                    batches.proving.remove(address);
+                }
+            }
             match res {
                 Ok(_) => anyhow::Ok(()),
                 Err(err) => {
@@ -169,6 +301,21 @@ impl ProverApiServer for ProverApiImpl {
         Ok(())
     }
 
+    async fn estimate_proof(&self, address: Address) -> Result<Estimate, ErrorObjectOwned> {
+        self.assert_in_trade_phase(address).await?;
+        info!(
+            "Estimating proof for TTC contract at address: {:#}",
+            address
+        );
+        match self.app_env.prover.estimate(address).await {
+            Ok(estimate) => Ok(estimate),
+            Err(err) => {
+                error!("Estimate errored with message {}", err);
+                Err(ErrorObject::owned(-32001, err.to_string(), None::<()>))
+            }
+        }
+    }
+
     async fn get_image_id_contract(&self) -> Result<String, ErrorObjectOwned> {
         Ok(self.app_env.image_id_contract.to_string())
     }
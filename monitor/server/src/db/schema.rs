@@ -29,4 +29,6 @@ pub struct Proof {
     pub address: Vec<u8>,
     pub proof: Vec<u8>,
     pub seal: Vec<u8>,
+    // Position of the contract's journal when the proof covers a batch of pools
+    pub batch_index: Option<i32>,
 }
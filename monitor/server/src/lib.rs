@@ -2,5 +2,4 @@ pub mod app_config;
 pub mod db;
 pub mod monitor;
 pub mod prover;
-pub mod ttc_contract;
 pub mod utils;
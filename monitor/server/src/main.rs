@@ -6,12 +6,13 @@ use jsonrpsee::{
 };
 use monitor_api::{
     rpc::MonitorApiServer,
-    types::{Proof, ProofStatus},
+    ttc_contract,
+    types::{Proof, ProofStatus, TradeOutcome, TradeStatus},
 };
 use monitor_server::{
     app_config::init_console_subscriber,
     db::{self, notify::JOB_CHANNEL, schema::JobStatus},
-    ttc_contract, utils,
+    utils,
 };
 use risc0_steel::alloy::primitives::Address;
 use std::{net::SocketAddr, sync::Arc};
@@ -51,6 +52,11 @@ mod app_env {
 
         #[arg(long, env = "PROVER_TIMEOUT", default_value = "120")]
         pub prover_timeout: u64,
+
+        /// Reject pools that take more cycles than this to prove, checked by executing the
+        /// guest before proving. Pools whose estimate fails are proved anyway.
+        #[arg(long, env = "MAX_CYCLES")]
+        pub max_cycles: Option<u64>,
     }
 
     impl AppConfig {
@@ -88,7 +94,7 @@ mod app_env {
                 db: db.clone(),
                 node_url: node_url.clone(),
                 prover: prover.clone(),
-                events_manager: EventsManager::new(node_url, prover, db),
+                events_manager: EventsManager::new(node_url, prover, db, app_config.max_cycles),
             })
         }
     }
@@ -132,6 +138,7 @@ impl MonitorApiServer for ProverApiImpl {
             Ok(Some(proof)) => Ok(Proof {
                 journal: proof.proof,
                 seal: proof.seal,
+                batch_index: proof.batch_index.map(|i| i as u32),
             }),
             Ok(None) => Err(ErrorObject::owned(
                 -32001,
@@ -160,6 +167,35 @@ impl MonitorApiServer for ProverApiImpl {
         }
     }
 
+    async fn get_trade_outcome(&self, address: Address) -> Result<TradeOutcome, ErrorObjectOwned> {
+        debug!("Getting trade outcome for address: {:#}", address);
+        let proof = match self
+            .app_env
+            .db
+            .get_proof_opt_by_address(address.as_slice())
+            .await
+        {
+            Ok(Some(proof)) => proof,
+            Ok(None) => {
+                return Err(ErrorObject::owned(
+                    -32001,
+                    "Proof not found".to_string(),
+                    None::<()>,
+                ))
+            }
+            Err(err) => return Err(ErrorObject::owned(-32001, err.to_string(), None::<()>)),
+        };
+        let batch_index = proof.batch_index.map(|i| i as u32);
+        let outcome = ttc_contract::decode_journal(&proof.proof, batch_index).and_then(|journal| {
+            Ok(TradeOutcome {
+                status: TradeStatus::try_from(journal.status)?,
+                preferences_digest: journal.preferencesDigest,
+                participant_count: journal.participantCount.try_into()?,
+            })
+        });
+        outcome.map_err(|err| ErrorObject::owned(-32001, err.to_string(), None::<()>))
+    }
+
     async fn watch_contract(&self, address: Address) -> Result<(), ErrorObjectOwned> {
         let provider = utils::create_provider(self.app_env.node_url.clone());
         let ttc = ttc_contract::ITopTradingCycle::new(address, provider);
@@ -58,7 +58,7 @@ impl Database {
     pub async fn get_proof_by_address(&self, address: &[u8]) -> Result<Proof, sqlx::Error> {
         sqlx::query_as(
             r#"
-            SELECT address, proof, seal 
+            SELECT address, proof, seal, batch_index
             FROM proofs 
             WHERE address = $1
         "#,
@@ -74,7 +74,7 @@ impl Database {
     ) -> Result<Option<Proof>, sqlx::Error> {
         sqlx::query_as(
             r#"
-            SELECT address, proof, seal 
+            SELECT address, proof, seal, batch_index
             FROM proofs 
             WHERE address = $1
         "#,
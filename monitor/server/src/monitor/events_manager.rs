@@ -1,11 +1,14 @@
 use super::db::Database;
 use crate::{
     db::schema::{Job, JobStatus},
-    prover::{remote::Prover, types::AsyncProverT},
-    ttc_contract::ITopTradingCycle::{self, PhaseChanged},
+    prover::{
+        remote::Prover,
+        types::{AsyncProverT, ProverT},
+    },
 };
 use chrono::{TimeZone, Utc};
 use futures::StreamExt;
+use monitor_api::ttc_contract::ITopTradingCycle::{self, PhaseChanged};
 use risc0_steel::alloy::{
     eips::BlockNumberOrTag,
     primitives::Address,
@@ -27,15 +30,18 @@ pub struct EventsManager {
     node_url: Url,
     prover: Prover,
     db: Database,
+    // Pools that take more cycles than this to prove are rejected instead of proved
+    max_cycles: Option<u64>,
 }
 
 impl EventsManager {
-    pub fn new(node_url: Url, prover: Prover, db: Database) -> Self {
+    pub fn new(node_url: Url, prover: Prover, db: Database, max_cycles: Option<u64>) -> Self {
         Self {
             events: Mutex::new(HashMap::new()),
             node_url,
             prover,
             db,
+            max_cycles,
         }
     }
 
@@ -70,6 +76,7 @@ impl EventsManager {
         let node_url = self.node_url.clone();
         let prover = self.prover.clone();
         let db = self.db.clone();
+        let max_cycles = self.max_cycles;
 
         let monitor_span = span!(
             Level::DEBUG,
@@ -110,15 +117,43 @@ impl EventsManager {
 
                                 debug!(parent: &monitor_span, "TTC contract as moved into trading phase");
 
+                                // Execute the guest first if there's a limit, so that an
+                                // oversized pool doesn't tie up the prover. Only a pool known
+                                // to be too big is rejected: if the estimate itself fails, e.g.
+                                // because the prover is briefly unreachable, prove it anyway
+                                // rather than leave it stuck in the Trade phase.
+                                let rejection = match max_cycles {
+                                    Some(max) => match prover.estimate(*ttc.address()).await {
+                                        Ok(estimate) if estimate.total_cycles > max => Some(format!(
+                                            "Proving would take {} cycles, more than the limit of {}",
+                                            estimate.total_cycles, max
+                                        )),
+                                        Ok(_) => None,
+                                        Err(e) => {
+                                            tracing::warn!(parent: &monitor_span, "Failed to estimate the proof, proving without a limit: {}", e);
+                                            None
+                                        }
+                                    },
+                                    None => None,
+                                };
+
                                 let job = Job {
                                     address: address.as_slice().to_vec(),
                                     block_number,
                                     block_timestamp,
-                                    status: JobStatus::Created,
-                                    error: None,
-                                    completed_at: None,
+                                    status: if rejection.is_some() {
+                                        JobStatus::Errored
+                                    } else {
+                                        JobStatus::Created
+                                    },
+                                    error: rejection.clone(),
+                                    completed_at: rejection.as_ref().map(|_| Utc::now()),
                                 };
                                 db.create_job(&job).await.map_err(anyhow::Error::new)?;
+                                if let Some(rejection) = rejection {
+                                    tracing::error!(parent: &monitor_span, "Not proving TTC contract: {}", rejection);
+                                    break;
+                                }
                                 debug!(parent: &monitor_span, "Created job for TTC contract. Sending prove request, this could take a while...");
                                 prover.prove_async(*ttc.address()).await?;
                                 debug!(parent: &monitor_span, "Successfully processed phase 2, stopping monitor for TTC contract");
@@ -60,22 +60,47 @@ impl Database {
     }
 
     // Proof-specific methods
-    pub async fn create_proof(&self, proof: &Proof) -> Result<(), sqlx::Error> {
-        sqlx::query(
-            r#"
-            INSERT INTO proofs (
-                address, proof, seal
-            ) VALUES (
-                $1, $2, $3
-            )
-        "#,
-        )
-        .bind(&proof.address)
-        .bind(&proof.proof)
-        .bind(&proof.seal)
-        .execute(&self.pool)
-        .await?;
 
-        Ok(())
+    // Store the proof of every pool in a batch and mark their jobs completed, in one
+    // transaction so that no pool is left behind if the database fails partway through
+    pub async fn complete_jobs(
+        &self,
+        proofs: &[Proof],
+        completed_at: DateTime<Utc>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for proof in proofs {
+            sqlx::query(
+                r#"
+                INSERT INTO proofs (
+                    address, proof, seal, batch_index
+                ) VALUES (
+                    $1, $2, $3, $4
+                )
+            "#,
+            )
+            .bind(&proof.address)
+            .bind(&proof.proof)
+            .bind(&proof.seal)
+            .bind(proof.batch_index)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                r#"
+                UPDATE jobs 
+                SET 
+                    status = $2, 
+                    error = NULL, 
+                    completed_at = $3
+                WHERE address = $1
+            "#,
+            )
+            .bind(&proof.address)
+            .bind(JobStatus::Completed)
+            .bind(completed_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
     }
 }
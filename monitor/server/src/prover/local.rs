@@ -1,6 +1,6 @@
-use crate::ttc_contract::ITopTradingCycle;
 use anyhow::{Context, Ok, Result};
 use methods::PROVABLE_TTC_ELF;
+use monitor_api::ttc_contract::ITopTradingCycle;
 use risc0_ethereum_contracts::encode_seal;
 use risc0_steel::{
     alloy::{
@@ -9,13 +9,13 @@ use risc0_steel::{
         providers::{Provider, ProviderBuilder},
         transports::http::{Client, Http},
     },
-    ethereum::{EthEvmEnv, ETH_SEPOLIA_CHAIN_SPEC},
+    ethereum::{EthChainSpec, EthEvmEnv, EthEvmInput},
 };
-use risc0_zkvm::{default_prover, ExecutorEnv, ProverOpts, VerifierContext};
+use risc0_zkvm::{default_executor, default_prover, ExecutorEnv, ProverOpts, VerifierContext};
 use tracing::{info, instrument};
 use url::Url;
 
-use super::types::{Proof, ProverT};
+use super::types::{Estimate, Proof, ProverT};
 
 pub fn create_provider(node_url: Url) -> impl Provider<Http<Client>, Ethereum> + Clone {
     ProviderBuilder::new().on_http(node_url)
@@ -24,48 +24,87 @@ pub fn create_provider(node_url: Url) -> impl Provider<Http<Client>, Ethereum> +
 #[derive(Clone)]
 pub struct Prover {
     node_url: Url,
+    chain_spec: EthChainSpec,
 }
 
 impl Prover {
-    pub fn new(node_url: &Url) -> Self {
+    pub fn new(node_url: &Url, chain_spec: EthChainSpec) -> Self {
         Self {
             node_url: node_url.clone(),
+            chain_spec,
         }
     }
-}
 
-impl ProverT for Prover {
-    #[instrument(skip_all, level = "info")]
-    async fn prove(&self, address: Address) -> Result<Proof> {
-        let evm_input = {
-            let provider = create_provider(self.node_url.clone());
-            let ttc = ITopTradingCycle::new(address, provider);
-            let block_number: u64 = {
-                let bn = ttc.tradeInitiatedAtBlock().call().await?;
-                u64::try_from(bn._0).context("block number is too large")
-            }?;
-            let mut env = EthEvmEnv::builder()
-                .rpc(self.node_url.clone())
-                .block_number(block_number)
-                .build()
-                .await?;
+    // Refuse to prove against a node on another chain, since the guest would execute with the
+    // wrong rules and commit the wrong chain ID
+    async fn check_chain_id(&self) -> Result<()> {
+        let chain_id = create_provider(self.node_url.clone())
+            .get_chain_id()
+            .await?;
+        anyhow::ensure!(
+            chain_id == self.chain_spec.chain_id,
+            "Node is on chain {} but the prover is configured for chain {}",
+            chain_id,
+            self.chain_spec.chain_id
+        );
+        Ok(())
+    }
 
-            //  The `with_chain_spec` method is used to specify the chain configuration.
-            env = env.with_chain_spec(&ETH_SEPOLIA_CHAIN_SPEC);
+    /// Preflight the calls the guest makes against the state at the trade block, giving the
+    /// pool's input to [`Prover::prove_inputs`]
+    pub async fn evm_input(&self, address: Address) -> Result<EthEvmInput> {
+        self.check_chain_id().await?;
+        let provider = create_provider(self.node_url.clone());
+        let ttc = ITopTradingCycle::new(address, provider);
+        let block_number: u64 = {
+            let bn = ttc.tradeInitiatedAtBlock().call().await?;
+            u64::try_from(bn._0).context("block number is too large")
+        }?;
+        let mut env = EthEvmEnv::builder()
+            .rpc(self.node_url.clone())
+            .block_number(block_number)
+            .build()
+            .await?;
 
-            let mut contract = risc0_steel::Contract::preflight(*ttc.address(), &mut env);
-            contract
-                .call_builder(&ITopTradingCycle::getAllTokenPreferencesCall {})
-                .call()
-                .await?;
+        //  The `with_chain_spec` method is used to specify the chain configuration.
+        env = env.with_chain_spec(&self.chain_spec);
 
-            env.into_input().await
-        }?;
-        info!("Running the guest with the constructed input:");
+        let mut contract = risc0_steel::Contract::preflight(*ttc.address(), &mut env);
+        contract
+            .call_builder(&ITopTradingCycle::getAllTokenPreferencesCall {})
+            .call()
+            .await?;
+        contract
+            .call_builder(&ITopTradingCycle::mechanismCall {})
+            .call()
+            .await?;
+
+        env.into_input().await
+    }
+
+    /// Prove several pools in one receipt. A single pool gets the same journal as
+    /// [`ProverT::prove`], and more than one get a `BatchJournal` with a journal for each pool,
+    /// in the order of `addresses`.
+    pub async fn prove_batch(&self, addresses: &[Address]) -> Result<Proof> {
+        let mut pools = Vec::with_capacity(addresses.len());
+        for &address in addresses {
+            pools.push((self.evm_input(address).await?, address));
+        }
+        self.prove_inputs(pools).await
+    }
+
+    /// Like [`Prover::prove_batch`], for pools that have already been preflighted
+    #[instrument(skip_all, level = "info")]
+    pub async fn prove_inputs(&self, pools: Vec<(EthEvmInput, Address)>) -> Result<Proof> {
+        info!(
+            "Running the guest with the constructed input for {} pools:",
+            pools.len()
+        );
+        let chain_spec = self.chain_spec.clone();
         let prove_info = tokio::task::spawn_blocking(move || {
             let env = ExecutorEnv::builder()
-                .write(&evm_input)?
-                .write(&address)?
+                .write(&chain_spec)?
+                .write(&pools)?
                 .build()
                 .unwrap();
 
@@ -77,6 +116,10 @@ impl ProverT for Prover {
             )
         })
         .await??;
+        info!(
+            "Proved in {} segments, {} total cycles ({} user cycles)",
+            prove_info.stats.segments, prove_info.stats.total_cycles, prove_info.stats.user_cycles
+        );
 
         let receipt = prove_info.receipt;
         let seal = encode_seal(&receipt).context("invalid receipt")?;
@@ -87,3 +130,39 @@ impl ProverT for Prover {
         Ok(proof)
     }
 }
+
+impl ProverT for Prover {
+    async fn prove(&self, address: Address) -> Result<Proof> {
+        self.prove_batch(&[address]).await
+    }
+
+    #[instrument(skip_all, level = "info")]
+    async fn estimate(&self, address: Address) -> Result<Estimate> {
+        let evm_input = self.evm_input(address).await?;
+        info!("Executing the guest with the constructed input:");
+        let chain_spec = self.chain_spec.clone();
+        let session = tokio::task::spawn_blocking(move || {
+            let env = ExecutorEnv::builder()
+                .write(&chain_spec)?
+                .write(&vec![(evm_input, address)])?
+                .build()
+                .unwrap();
+
+            default_executor().execute(env, PROVABLE_TTC_ELF)
+        })
+        .await??;
+        // Every segment is padded to a power of two cycles, which is what proving pays for
+        let estimate = Estimate {
+            segments: session.segments.len() as u64,
+            total_cycles: session.segments.iter().map(|s| 1u64 << s.po2).sum(),
+            user_cycles: session.segments.iter().map(|s| s.cycles as u64).sum(),
+            journal: session.journal.bytes,
+        };
+        info!(
+            "Executed in {} segments, {} total cycles ({} user cycles)",
+            estimate.segments, estimate.total_cycles, estimate.user_cycles
+        );
+
+        Ok(estimate)
+    }
+}
@@ -1,9 +1,10 @@
 use super::{
     rpc::ProverApiClient,
-    types::{AsyncProverT, Proof, ProverT},
+    types::{AsyncProverT, Estimate, Proof, ProverT},
 };
-use crate::{ttc_contract, utils};
+use crate::utils;
 use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
+use monitor_api::ttc_contract;
 use risc0_steel::alloy::{
     network::Ethereum,
     primitives::Address,
@@ -60,6 +61,14 @@ impl ProverT for Prover {
             seal: p.seal,
         })
     }
+
+    async fn estimate(&self, address: Address) -> anyhow::Result<Estimate> {
+        let provider = utils::create_provider(self.node_url.clone());
+        assert_in_trade_phase(provider, address).await?;
+        ProverApiClient::estimate_proof(&self.client, address)
+            .await
+            .map_err(|e| anyhow::anyhow!("Prover estimate_proof request failed: {:#}", e))
+    }
 }
 
 impl AsyncProverT for Prover {
@@ -1,4 +1,4 @@
-use super::types::Proof;
+use super::types::{Estimate, Proof};
 use jsonrpsee::{proc_macros::rpc, types::ErrorObjectOwned};
 use risc0_steel::alloy::primitives::Address;
 
@@ -10,6 +10,10 @@ pub trait ProverApi {
     #[method(name = "proveAsync")]
     async fn prove_async(&self, address: Address) -> Result<(), ErrorObjectOwned>;
 
+    /// Run the guest without proving, to see what a proof would cost and commit to
+    #[method(name = "estimateProof")]
+    async fn estimate_proof(&self, address: Address) -> Result<Estimate, ErrorObjectOwned>;
+
     #[method(name = "getImageIDContract")]
     async fn get_image_id_contract(&self) -> Result<String, ErrorObjectOwned>;
 
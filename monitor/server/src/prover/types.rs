@@ -7,9 +7,20 @@ pub struct Proof {
     pub seal: Vec<u8>,
 }
 
+/// What proving would take, measured by running the guest in the executor without proving
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Estimate {
+    pub segments: u64,
+    pub total_cycles: u64,
+    pub user_cycles: u64,
+    /// The journal the proof would commit to
+    pub journal: Vec<u8>,
+}
+
 #[allow(async_fn_in_trait)]
 pub trait ProverT {
     async fn prove(&self, address: Address) -> anyhow::Result<Proof>;
+    async fn estimate(&self, address: Address) -> anyhow::Result<Estimate>;
 }
 
 #[allow(async_fn_in_trait)]
@@ -0,0 +1,76 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use hashbrown::HashMap;
+use ttc::strict::{PreferenceGraph, Preferences};
+
+// xorshift64, so that the benchmark inputs are the same on every run
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn random_preferences(n: usize, max_len: usize) -> Preferences<u32> {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d ^ n as u64);
+    let prefs: HashMap<u32, Vec<u32>> = (0..n as u32)
+        .map(|v| {
+            let len = rng.below(max_len + 1);
+            let mut ps: Vec<u32> = (0..len).map(|_| rng.below(n) as u32).collect();
+            let mut seen = std::collections::HashSet::new();
+            ps.retain(|x| seen.insert(*x));
+            (v, ps)
+        })
+        .collect();
+    Preferences::new(prefs).unwrap()
+}
+
+fn bench_solve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solve_preferences");
+    for n in [100, 1_000, 10_000] {
+        for max_len in [10, 100] {
+            let prefs = random_preferences(n, max_len);
+            group.bench_with_input(
+                BenchmarkId::new(format!("max_len={}", max_len), n),
+                &prefs,
+                |b, prefs| {
+                    b.iter(|| {
+                        let mut g = PreferenceGraph::new(prefs.clone()).unwrap();
+                        g.solve_preferences().unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_rounds(c: &mut Criterion) {
+    let mut group = c.benchmark_group("solve_rounds");
+    for n in [100, 1_000, 10_000] {
+        for max_len in [10, 100] {
+            let prefs = random_preferences(n, max_len);
+            group.bench_with_input(
+                BenchmarkId::new(format!("max_len={}", max_len), n),
+                &prefs,
+                |b, prefs| {
+                    b.iter(|| {
+                        let mut g = PreferenceGraph::new(prefs.clone()).unwrap();
+                        g.solve_rounds().unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_solve, bench_rounds);
+criterion_main!(benches);
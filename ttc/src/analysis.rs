@@ -0,0 +1,249 @@
+use crate::{mechanism::Mechanism, strict::Preferences};
+use alloc::{vec, vec::Vec};
+use core::{
+    fmt::{Debug, Display},
+    hash::Hash,
+};
+use hashbrown::HashSet;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum AnalysisError<V: Display, E: core::error::Error> {
+    #[error("{} is not a participant", _0)]
+    UnknownParticipant(V),
+    #[error("Mechanism failed: {}", _0)]
+    Mechanism(E),
+}
+
+/// A report that gets the participant an item they truly prefer to the one they get by
+/// reporting honestly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manipulation<V> {
+    pub report: Vec<V>,
+    pub received: V,
+}
+
+#[derive(Debug, Clone)]
+pub struct ManipulationReport<V> {
+    pub participant: V,
+    /// What the participant receives when reporting their true preferences
+    pub truthful: V,
+    /// The number of distinct alternative reports that were tried
+    pub reports_checked: usize,
+    pub counterexamples: Vec<Manipulation<V>>,
+}
+
+impl<V> ManipulationReport<V> {
+    /// Whether every alternative report did at most as well as the truthful one.
+    pub fn truthful_is_optimal(&self) -> bool {
+        self.counterexamples.is_empty()
+    }
+}
+
+/// Search the alternative reports available to `participant`, keeping everyone else's
+/// preferences fixed, for one that beats reporting truthfully under `mechanism`. The candidates
+/// are every truncation, single drop and pairwise swap of the participant's true list, plus
+/// every ordered list of at most `max_len` items from the pool. The latter grows like
+/// `n^max_len`, so keep `max_len` small for large pools.
+///
+/// Outcomes are compared using the participant's true preferences, under which their own item
+/// beats anything ranked after it or left off the list.
+pub fn explore_manipulations<M, V>(
+    mechanism: &M,
+    prefs: &Preferences<V>,
+    participant: &V,
+    max_len: usize,
+) -> Result<ManipulationReport<V>, AnalysisError<V, M::Error>>
+where
+    M: Mechanism<V>,
+    V: Eq + Hash + Clone + Display,
+{
+    let truth = prefs
+        .get(participant)
+        .ok_or_else(|| AnalysisError::UnknownParticipant(participant.clone()))?;
+    let outcome = |report: &Vec<V>| -> Result<V, AnalysisError<V, M::Error>> {
+        let mut prefs = prefs.clone();
+        prefs.prefs.insert(participant.clone(), report.clone());
+        let allocation = mechanism
+            .allocate(&prefs)
+            .map_err(AnalysisError::Mechanism)?;
+        Ok(allocation
+            .allocation
+            .get(participant)
+            .cloned()
+            .unwrap_or_else(|| participant.clone()))
+    };
+    let value = |item: &V| {
+        prefs
+            .acceptable_rank(participant, item)
+            .unwrap_or(usize::MAX)
+    };
+
+    let truthful = outcome(truth)?;
+    let mut reports_checked = 0;
+    let mut counterexamples = vec![];
+    let mut seen = HashSet::new();
+    seen.insert(truth.clone());
+    for report in alternative_reports(prefs, participant, max_len) {
+        if !seen.insert(report.clone()) {
+            continue;
+        }
+        reports_checked += 1;
+        let received = outcome(&report)?;
+        if value(&received) < value(&truthful) {
+            counterexamples.push(Manipulation { report, received });
+        }
+    }
+
+    Ok(ManipulationReport {
+        participant: participant.clone(),
+        truthful,
+        reports_checked,
+        counterexamples,
+    })
+}
+
+fn alternative_reports<V>(
+    prefs: &Preferences<V>,
+    participant: &V,
+    max_len: usize,
+) -> impl Iterator<Item = Vec<V>>
+where
+    V: Eq + Hash + Clone,
+{
+    let truth = prefs.prefs[participant].clone();
+    let n = truth.len();
+
+    let truncations = (0..n).map({
+        let truth = truth.clone();
+        move |k| truth[..k].to_vec()
+    });
+    let drops = (0..n).map({
+        let truth = truth.clone();
+        move |i| {
+            let mut report = truth.clone();
+            report.remove(i);
+            report
+        }
+    });
+    let swaps = (0..n)
+        .flat_map(move |i| (i + 1..n).map(move |j| (i, j)))
+        .map(move |(i, j)| {
+            let mut report = truth.clone();
+            report.swap(i, j);
+            report
+        });
+
+    let mut items: Vec<V> = prefs.prefs.keys().cloned().collect();
+    // Listing your own item first is the same as listing nothing
+    items.retain(|v| v != participant);
+    let permutations = (0..=max_len.min(items.len())).flat_map(move |k| {
+        let mut reports = vec![];
+        permutations_of(
+            &items,
+            k,
+            &mut vec![],
+            &mut vec![false; items.len()],
+            &mut reports,
+        );
+        reports
+    });
+
+    truncations.chain(drops).chain(swaps).chain(permutations)
+}
+
+// All ordered selections of `k` distinct items
+fn permutations_of<V: Clone>(
+    items: &[V],
+    k: usize,
+    current: &mut Vec<V>,
+    used: &mut Vec<bool>,
+    out: &mut Vec<Vec<V>>,
+) {
+    if current.len() == k {
+        out.push(current.clone());
+        return;
+    }
+    for i in 0..items.len() {
+        if used[i] {
+            continue;
+        }
+        used[i] = true;
+        current.push(items[i].clone());
+        permutations_of(items, k, current, used, out);
+        current.pop();
+        used[i] = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        mechanism::TopTradingCycle,
+        strict::{Allocation, TTCError},
+    };
+    use proptest::prelude::*;
+
+    // TTC run on everyone's lists backwards, which rewards misreporting
+    struct WorstFirst;
+
+    impl Mechanism<u32> for WorstFirst {
+        type Error = TTCError;
+
+        fn allocate(&self, prefs: &Preferences<u32>) -> Result<Allocation<u32>, TTCError> {
+            let reversed = Preferences {
+                prefs: prefs
+                    .prefs
+                    .iter()
+                    .map(|(k, vs)| (*k, vs.iter().rev().cloned().collect()))
+                    .collect(),
+            };
+            TopTradingCycle.allocate(&reversed)
+        }
+    }
+
+    fn example() -> Preferences<u32> {
+        let prefs = vec![(1, vec![2, 3]), (2, vec![1, 3]), (3, vec![1, 2])];
+        Preferences::new(prefs.into_iter().collect()).unwrap()
+    }
+
+    #[test]
+    fn ttc_is_not_manipulable() {
+        let prefs = example();
+        for p in [1, 2, 3] {
+            let report = explore_manipulations(&TopTradingCycle, &prefs, &p, 2).unwrap();
+            assert!(report.truthful_is_optimal(), "{:?}", report);
+            assert!(report.reports_checked > 0);
+        }
+    }
+
+    #[test]
+    fn finds_counterexample() {
+        let prefs = vec![(1, vec![2, 3]), (2, vec![1]), (3, vec![1])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let report = explore_manipulations(&WorstFirst, &prefs, &1, 2).unwrap();
+        assert_eq!(report.truthful, 3);
+        assert!(report.counterexamples.contains(&Manipulation {
+            report: vec![3, 2],
+            received: 2
+        }));
+        assert!(matches!(
+            explore_manipulations(&WorstFirst, &prefs, &4, 2),
+            Err(AnalysisError::UnknownParticipant(4))
+        ));
+    }
+
+    proptest! {
+    #[test]
+    fn test_ttc_is_strategy_proof(
+        (p, participant) in Preferences::<u32>::arbitrary_with(Some(2..=8)).prop_flat_map(|p| {
+            let participants: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), prop::sample::select(participants))
+        }))
+      { let report = explore_manipulations(&TopTradingCycle, &p, &participant, 3).unwrap();
+        prop_assert!(report.truthful_is_optimal(), "Found a profitable misreport: {:?}", report);
+      }
+    }
+}
@@ -0,0 +1,378 @@
+use crate::strict::{Cycle, Preferences, TTCError};
+use alloc::{format, vec, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::HashMap;
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+/// Solves the housing market using only cycles of at most `max_len` participants, e.g. pairwise
+/// swaps for `max_len = 2`. Among all allocations made of such cycles, it picks one that
+/// maximizes total welfare, where a participant who receives the item at position `r` of their
+/// list scores `own - r` and `own` is the position of their own item (or the length of the list
+/// if they didn't rank it). Keeping your own item scores 0.
+///
+/// Because every participant's score strictly increases with how much they like their item, a
+/// welfare-maximizing allocation is also Pareto efficient among allocations with short cycles.
+/// Only items ranked above the participant's own item are ever traded, so the result is
+/// individually rational. Ties are broken deterministically by the order of the participants.
+///
+/// The bound comes at a cost compared to [`crate::strict::PreferenceGraph`]:
+/// - It is not strategy-proof. Listing extra items you don't want raises the score of the trades
+///   you do want, which can win you a better cycle.
+/// - The result needn't be in the core. A coalition can block it with a cycle longer than
+///   `max_len`, or with a short cycle that loses to a higher scoring one elsewhere.
+/// - It is not Pareto efficient among all allocations, only among those with short cycles.
+///
+/// Finding the best set of disjoint cycles is NP-hard for `max_len >= 3`. The cycles are
+/// enumerated up front and packed with branch and bound, which is fast for the bounds this mode
+/// is meant for, but can be exponential in the worst case.
+pub struct PreferenceGraph<V> {
+    nodes: Vec<V>,
+    // Scores of each participant's acceptable items, as indices into `nodes`
+    weights: Vec<HashMap<usize, u64>>,
+    max_len: usize,
+}
+
+// A candidate cycle as indices into `nodes`, with its total score
+struct Candidate {
+    members: Vec<usize>,
+    weight: u64,
+}
+
+impl<V> PreferenceGraph<V>
+where
+    V: Ord + Display + Hash + Copy,
+{
+    pub fn new(prefs: Preferences<V>, max_len: usize) -> Result<Self, TTCError> {
+        let mut nodes: Vec<V> = prefs.prefs.keys().cloned().collect();
+        if nodes.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        nodes.sort();
+        let index: HashMap<V, usize> = nodes.iter().enumerate().map(|(i, &v)| (v, i)).collect();
+        let weights = nodes
+            .iter()
+            .map(|v| {
+                let list = &prefs.prefs[v];
+                let own = list.iter().position(|x| x == v).unwrap_or(list.len());
+                list.iter()
+                    .take(own)
+                    .enumerate()
+                    .map(|(r, x)| {
+                        index
+                            .get(x)
+                            .map(|&i| (i, (own - r) as u64))
+                            .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", v, x)))
+                    })
+                    .collect()
+            })
+            .collect::<Result<Vec<HashMap<usize, u64>>, TTCError>>()?;
+        Ok(Self {
+            nodes,
+            weights,
+            max_len,
+        })
+    }
+
+    // Every cycle of 2 to `max_len` participants in which everyone gets an acceptable item,
+    // grouped by the smallest participant in the cycle
+    fn candidates(&self) -> Vec<Vec<Candidate>> {
+        let n = self.nodes.len();
+        let mut by_min: Vec<Vec<Candidate>> = (0..n).map(|_| Vec::new()).collect();
+        let mut path = Vec::new();
+        for (start, out) in by_min.iter_mut().enumerate() {
+            path.push(start);
+            self.extend(start, &mut path, 0, out);
+            path.clear();
+        }
+        for cycles in by_min.iter_mut() {
+            // Try the best cycles first so that good solutions bound the search early
+            cycles.sort_by(|a, b| b.weight.cmp(&a.weight));
+        }
+        by_min
+    }
+
+    fn extend(&self, start: usize, path: &mut Vec<usize>, weight: u64, out: &mut Vec<Candidate>) {
+        let last = *path.last().unwrap();
+        let mut next: Vec<(&usize, &u64)> = self.weights[last].iter().collect();
+        next.sort();
+        for (&v, &w) in next {
+            if v == start && path.len() >= 2 {
+                out.push(Candidate {
+                    members: path.clone(),
+                    weight: weight + w,
+                });
+            } else if v > start && path.len() < self.max_len && !path.contains(&v) {
+                path.push(v);
+                self.extend(start, path, weight + w, out);
+                path.pop();
+            }
+        }
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(&self) -> Result<Vec<Cycle<V>>, TTCError> {
+        let n = self.nodes.len();
+        let candidates = self.candidates();
+        let mut search = Search {
+            candidates: &candidates,
+            weights: &self.weights,
+            covered: vec![false; n],
+            best: vec![0; n],
+            chosen: Vec::new(),
+            weight: 0,
+            solution: Vec::new(),
+            solution_weight: 0,
+        };
+        search.run(0);
+
+        let mut covered = vec![false; n];
+        let mut res = Vec::new();
+        for c in search.solution.iter() {
+            for &v in &c.members {
+                covered[v] = true;
+            }
+            res.push(Cycle {
+                values: c.members.iter().map(|&v| self.nodes[v]).collect(),
+            });
+        }
+        // Everyone else keeps their own item
+        for (v, _) in covered.iter().enumerate().filter(|(_, c)| !**c) {
+            res.push(Cycle {
+                values: vec![self.nodes[v]],
+            });
+        }
+        Ok(res)
+    }
+}
+
+// Branch and bound over which cycle, if any, covers the smallest undecided participant
+struct Search<'a> {
+    candidates: &'a [Vec<Candidate>],
+    weights: &'a [HashMap<usize, u64>],
+    covered: Vec<bool>,
+    // Scratch space for `bound`, so that it doesn't allocate at every node
+    best: Vec<u64>,
+    chosen: Vec<&'a Candidate>,
+    weight: u64,
+    solution: Vec<&'a Candidate>,
+    solution_weight: u64,
+}
+
+fn available(covered: &[bool], c: &Candidate) -> bool {
+    c.members.iter().all(|&v| !covered[v])
+}
+
+impl Search<'_> {
+    fn run(&mut self, from: usize) {
+        let Some(u) = (from..self.covered.len()).find(|&v| !self.covered[v]) else {
+            if self.weight > self.solution_weight {
+                self.solution = self.chosen.clone();
+                self.solution_weight = self.weight;
+            }
+            return;
+        };
+        if self.weight + self.bound(u) <= self.solution_weight {
+            return;
+        }
+        let candidates = self.candidates;
+        for c in candidates[u].iter() {
+            if !self.available(c) {
+                continue;
+            }
+            self.cover(c, true);
+            self.chosen.push(c);
+            self.run(u + 1);
+            self.chosen.pop();
+            self.cover(c, false);
+        }
+        // Or `u` keeps its own item
+        self.covered[u] = true;
+        self.run(u + 1);
+        self.covered[u] = false;
+    }
+
+    fn available(&self, c: &Candidate) -> bool {
+        available(&self.covered, c)
+    }
+
+    // The most the undecided participants can add: each gets the best item it could get in any
+    // cycle that's still available. Everyone before `u` is decided, so only cycles whose
+    // smallest participant is `u` or later can be available.
+    fn bound(&mut self, u: usize) -> u64 {
+        let best = &mut self.best[u..];
+        best.fill(0);
+        for c in self.candidates[u..].iter().flatten() {
+            if !available(&self.covered, c) {
+                continue;
+            }
+            for (i, &v) in c.members.iter().enumerate() {
+                let next = c.members[(i + 1) % c.members.len()];
+                best[v - u] = best[v - u].max(self.weights[v][&next]);
+            }
+        }
+        best.iter().sum()
+    }
+
+    fn cover(&mut self, c: &Candidate, covered: bool) {
+        for &v in &c.members {
+            self.covered[v] = covered;
+        }
+        if covered {
+            self.weight += c.weight;
+        } else {
+            self.weight -= c.weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        analysis::explore_manipulations,
+        mechanism::BoundedExchange,
+        strict::{self, Allocation},
+    };
+    use itertools::Itertools;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    fn solve(prefs: Vec<(u32, Vec<u32>)>, max_len: usize) -> Allocation<u32> {
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let g = PreferenceGraph::new(prefs, max_len).unwrap();
+        Allocation::from(g.solve_preferences().unwrap())
+    }
+
+    // Total score of an allocation, as defined on `PreferenceGraph`
+    fn welfare(prefs: &Preferences<u32>, alloc: &Allocation<u32>) -> u64 {
+        alloc
+            .allocation
+            .iter()
+            .filter(|(v, x)| v != x)
+            .map(|(v, x)| {
+                let list = &prefs.prefs[v];
+                let own = list.iter().position(|y| y == v).unwrap_or(list.len());
+                (own - prefs.acceptable_rank(v, x).unwrap()) as u64
+            })
+            .sum()
+    }
+
+    // The best packing of `candidates`, found without any bound
+    fn exhaustive(candidates: &[Vec<Candidate>], covered: &mut [bool], from: usize) -> u64 {
+        let Some(u) = (from..covered.len()).find(|&v| !covered[v]) else {
+            return 0;
+        };
+        covered[u] = true;
+        let mut best = exhaustive(candidates, covered, u + 1);
+        covered[u] = false;
+        for c in candidates[u].iter() {
+            if !available(covered, c) {
+                continue;
+            }
+            c.members.iter().for_each(|&v| covered[v] = true);
+            best = best.max(c.weight + exhaustive(candidates, covered, u + 1));
+            c.members.iter().for_each(|&v| covered[v] = false);
+        }
+        best
+    }
+
+    #[test]
+    fn bounded_test() {
+        let prefs = vec![(1, vec![2]), (2, vec![3]), (3, vec![1])];
+        // The only trade is a 3-cycle
+        let alloc = solve(prefs.clone(), 2);
+        assert!(alloc.allocation.iter().all(|(v, x)| v == x));
+        let alloc = solve(prefs, 3);
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 2), (2, 3), (3, 1)].into_iter().collect()
+        );
+
+        // Swapping with 2 makes 1 happier than swapping with 3, and 2 and 3 are equally happy
+        let prefs = vec![(1, vec![2, 3]), (2, vec![1]), (3, vec![1])];
+        let alloc = solve(prefs, 2);
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 2), (2, 1), (3, 3)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn not_strategy_proof() {
+        // 1 would rather swap with 3, so 2 keeps its item. By listing items it doesn't want, 2
+        // inflates the score of swapping with 1 and wins the swap.
+        let prefs = vec![(1, vec![3, 2]), (2, vec![1]), (3, vec![1]), (4, vec![])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let report = explore_manipulations(&BoundedExchange { max_len: 2 }, &prefs, &2, 3).unwrap();
+        assert_eq!(report.truthful, 2);
+        assert!(report.counterexamples.iter().any(|m| m.received == 1));
+    }
+
+    proptest! {
+    #[test]
+    // The packing is exponential in the worst case, and random preferences are much denser than
+    // real ones, so keep the markets small
+    fn test_bounded_is_valid(p in Preferences::<u32>::arbitrary_with(Some(2..=12)), max_len in 1..4usize)
+      { let g = PreferenceGraph::new(p.clone(), max_len).unwrap();
+        let cycles = g.solve_preferences().unwrap();
+        prop_assert!(cycles.iter().all(|c| c.values.len() <= max_len.max(1)));
+        let alloc = Allocation::from(cycles);
+        prop_assert_eq!(alloc.allocation.len(), p.prefs.len());
+        let received: HashSet<u32> = alloc.allocation.values().cloned().collect();
+        prop_assert_eq!(received.len(), p.prefs.len());
+        for (v, x) in alloc.allocation.iter() {
+            prop_assert!(p.acceptable_rank(v, x).is_some());
+        }
+        // TTC is individually rational too, so when its cycles are short enough it can't beat us
+        let ttc = strict::PreferenceGraph::new(p.clone()).unwrap().solve_preferences().unwrap();
+        if ttc.iter().all(|c| c.values.len() <= max_len) {
+            prop_assert!(welfare(&p, &alloc) >= welfare(&p, &Allocation::from(ttc)));
+        }
+      }
+
+    #[test]
+    // The bound only prunes branches that can't beat the best packing found so far
+    fn test_bound_keeps_the_optimum(p in Preferences::<u32>::arbitrary_with(Some(2..=9)), max_len in 2..4usize)
+      { let g = PreferenceGraph::new(p.clone(), max_len).unwrap();
+        let alloc = Allocation::from(g.solve_preferences().unwrap());
+        let optimum = exhaustive(&g.candidates(), &mut vec![false; p.prefs.len()], 0);
+        prop_assert_eq!(welfare(&p, &alloc), optimum);
+      }
+
+    #[test]
+    fn test_bounded_is_optimal(p in Preferences::<u32>::arbitrary_with(Some(1..=6)), max_len in 1..5usize)
+      { let g = PreferenceGraph::new(p.clone(), max_len).unwrap();
+        let alloc = Allocation::from(g.solve_preferences().unwrap());
+        // Brute force over every individually rational permutation with short enough cycles
+        let participants: Vec<u32> = p.prefs.keys().cloned().sorted().collect();
+        let optimum = participants
+            .iter()
+            .cloned()
+            .permutations(participants.len())
+            .filter_map(|items| {
+                let alloc = Allocation {
+                    allocation: participants.iter().cloned().zip(items).collect(),
+                };
+                let rational = alloc
+                    .allocation
+                    .iter()
+                    .all(|(v, x)| p.acceptable_rank(v, x).is_some());
+                let short = participants.iter().all(|&v| {
+                    let mut len = 1;
+                    let mut x = alloc.allocation[&v];
+                    while x != v {
+                        x = alloc.allocation[&x];
+                        len += 1;
+                    }
+                    len <= max_len.max(1)
+                });
+                (rational && short).then(|| welfare(&p, &alloc))
+            })
+            .max()
+            .unwrap();
+        prop_assert_eq!(welfare(&p, &alloc), optimum);
+      }
+    }
+}
@@ -0,0 +1,223 @@
+use crate::trace::{Round, Trace};
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    string::String,
+};
+use core::fmt::{self, Display};
+
+/// Renders a [`Trace`] as a Graphviz digraph with one cluster per round. Every remaining
+/// participant has an edge to the owner of the item it points at, the cycles that leave in that
+/// round are drawn in bold red, and participants that have to point elsewhere next round are
+/// dashed. Created by [`Trace::dot`].
+pub struct Dot<'a, V, F> {
+    trace: &'a Trace<V>,
+    label: F,
+}
+
+/// Renders a [`Trace`] as a Mermaid flowchart, laid out like [`Dot`]. Created by
+/// [`Trace::mermaid`].
+pub struct Mermaid<'a, V, F> {
+    trace: &'a Trace<V>,
+    label: F,
+}
+
+impl<V: Ord> Trace<V> {
+    /// Render the trace in DOT, labelling every participant with `label`, e.g. to shorten token
+    /// hashes.
+    pub fn dot<F: Fn(&V) -> String>(&self, label: F) -> Dot<'_, V, F> {
+        Dot { trace: self, label }
+    }
+
+    /// Render the trace in Mermaid, labelling every participant with `label`.
+    pub fn mermaid<F: Fn(&V) -> String>(&self, label: F) -> Mermaid<'_, V, F> {
+        Mermaid { trace: self, label }
+    }
+
+    // Node identifiers have to be unique across rounds, so they combine the round with the
+    // participant's position among everyone in the first round
+    fn ids(&self) -> BTreeMap<&V, usize> {
+        self.rounds
+            .first()
+            .map(|r| r.pointers.keys().enumerate().map(|(i, v)| (v, i)).collect())
+            .unwrap_or_default()
+    }
+}
+
+// The members of the round's cycles, whose pointers are the cycles' edges
+fn in_cycle<V: Ord>(round: &Round<V>) -> BTreeSet<&V> {
+    round.cycles.iter().flat_map(|c| c.values()).collect()
+}
+
+// Escape a label for a double quoted DOT string
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Mermaid has no escapes inside quoted labels, only HTML entities
+fn escape_mermaid(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
+
+impl<V: Ord, F: Fn(&V) -> String> Display for Dot<'_, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids = self.trace.ids();
+        writeln!(f, "digraph ttc {{")?;
+        writeln!(f, "  rankdir=LR;")?;
+        for round in &self.trace.rounds {
+            let r = round.round;
+            let cycle = in_cycle(round);
+            writeln!(f, "  subgraph cluster_{} {{", r)?;
+            writeln!(f, "    label=\"Round {}\";", r)?;
+            for v in round.pointers.keys() {
+                let style = if cycle.contains(v) {
+                    ", style=filled, fillcolor=lightpink"
+                } else if round.redirected.contains(v) {
+                    ", style=dashed"
+                } else {
+                    ""
+                };
+                writeln!(
+                    f,
+                    "    r{}_{} [label=\"{}\"{}];",
+                    r,
+                    ids[v],
+                    escape_dot(&(self.label)(v)),
+                    style
+                )?;
+            }
+            for (v, w) in &round.pointers {
+                let style = if cycle.contains(v) {
+                    " [color=red, penwidth=2]"
+                } else {
+                    ""
+                };
+                writeln!(f, "    r{}_{} -> r{}_{}{};", r, ids[v], r, ids[w], style)?;
+            }
+            writeln!(f, "  }}")?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl<V: Ord, F: Fn(&V) -> String> Display for Mermaid<'_, V, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ids = self.trace.ids();
+        writeln!(f, "flowchart LR")?;
+        for round in &self.trace.rounds {
+            let r = round.round;
+            let cycle = in_cycle(round);
+            writeln!(f, "  subgraph round_{} [Round {}]", r, r)?;
+            for v in round.pointers.keys() {
+                let class = if cycle.contains(v) {
+                    ":::cycle"
+                } else if round.redirected.contains(v) {
+                    ":::redirected"
+                } else {
+                    ""
+                };
+                writeln!(
+                    f,
+                    "    r{}_{}[\"{}\"]{}",
+                    r,
+                    ids[v],
+                    escape_mermaid(&(self.label)(v)),
+                    class
+                )?;
+            }
+            for (v, w) in &round.pointers {
+                let arrow = if cycle.contains(v) { "==>" } else { "-->" };
+                writeln!(f, "    r{}_{} {} r{}_{}", r, ids[v], arrow, r, ids[w])?;
+            }
+            writeln!(f, "  end")?;
+        }
+        writeln!(f, "  classDef cycle fill:#ffb6c1,stroke:#ff0000")?;
+        writeln!(f, "  classDef redirected stroke-dasharray: 5 5")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::{PreferenceGraph, Preferences};
+    use proptest::prelude::*;
+
+    fn example() -> Trace<u32> {
+        // 1 and 2 swap in round 1, then 3 has to settle for 4's item and 4 for 3's
+        let prefs = vec![(1, vec![2]), (2, vec![1]), (3, vec![1, 4]), (4, vec![3])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        PreferenceGraph::new(prefs).unwrap().solve_traced().unwrap()
+    }
+
+    #[test]
+    fn dot_test() {
+        let dot = example().dot(|v| format!("S{}", v)).to_string();
+        assert_eq!(
+            dot,
+            r#"digraph ttc {
+  rankdir=LR;
+  subgraph cluster_1 {
+    label="Round 1";
+    r1_0 [label="S1", style=filled, fillcolor=lightpink];
+    r1_1 [label="S2", style=filled, fillcolor=lightpink];
+    r1_2 [label="S3", style=dashed];
+    r1_3 [label="S4"];
+    r1_0 -> r1_1 [color=red, penwidth=2];
+    r1_1 -> r1_0 [color=red, penwidth=2];
+    r1_2 -> r1_0;
+    r1_3 -> r1_2;
+  }
+  subgraph cluster_2 {
+    label="Round 2";
+    r2_2 [label="S3", style=filled, fillcolor=lightpink];
+    r2_3 [label="S4", style=filled, fillcolor=lightpink];
+    r2_2 -> r2_3 [color=red, penwidth=2];
+    r2_3 -> r2_2 [color=red, penwidth=2];
+  }
+}
+"#
+        );
+    }
+
+    #[test]
+    fn mermaid_test() {
+        let mermaid = example().mermaid(|v| format!("\"{}\"", v)).to_string();
+        assert_eq!(
+            mermaid,
+            r##"flowchart LR
+  subgraph round_1 [Round 1]
+    r1_0["#quot;1#quot;"]:::cycle
+    r1_1["#quot;2#quot;"]:::cycle
+    r1_2["#quot;3#quot;"]:::redirected
+    r1_3["#quot;4#quot;"]
+    r1_0 ==> r1_1
+    r1_1 ==> r1_0
+    r1_2 --> r1_0
+    r1_3 --> r1_2
+  end
+  subgraph round_2 [Round 2]
+    r2_2["#quot;3#quot;"]:::cycle
+    r2_3["#quot;4#quot;"]:::cycle
+    r2_2 ==> r2_3
+    r2_3 ==> r2_2
+  end
+  classDef cycle fill:#ffb6c1,stroke:#ff0000
+  classDef redirected stroke-dasharray: 5 5
+"##
+        );
+    }
+
+    proptest! {
+    #[test]
+    fn test_dot_has_every_pointer(p in Preferences::<u32>::arbitrary())
+      { let trace = PreferenceGraph::new(p).unwrap().solve_traced().unwrap();
+        let dot = trace.dot(|v| v.to_string()).to_string();
+        let edges = dot.lines().filter(|l| l.contains("->")).count();
+        let bold = dot.lines().filter(|l| l.contains("->") && l.contains("color=red")).count();
+        let pointers: usize = trace.rounds.iter().map(|r| r.pointers.len()).sum();
+        prop_assert_eq!(edges, pointers);
+        // Everyone leaves in exactly one cycle
+        prop_assert_eq!(bold, trace.rounds[0].pointers.len());
+      }
+    }
+}
@@ -0,0 +1,184 @@
+//! Random preference profiles that look more like real demand than the uniform
+//! [`Arbitrary`](proptest::arbitrary::Arbitrary) instance for [`Preferences`]. Participants are
+//! `0..n`, each participant's own item is left out of its list (so it's only kept as a last
+//! resort), and items are numbered in order of popularity where that makes sense.
+use crate::strict::Preferences;
+use proptest::prelude::*;
+use std::ops::RangeInclusive;
+
+// Order `items` by weighted sampling without replacement, using one uniform sample per item
+// (Efraimidis and Spirakis): higher weights are more likely to come first.
+fn weighted_order(items: Vec<u32>, weight: impl Fn(u32) -> f64, samples: &[f64]) -> Vec<u32> {
+    let mut keyed: Vec<(f64, u32)> = items
+        .into_iter()
+        .zip(samples)
+        .map(|(x, u)| (u.powf(1.0 / weight(x)), x))
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    keyed.into_iter().map(|(_, x)| x).collect()
+}
+
+// Build a profile from one row of uniform samples per participant
+fn profile(
+    n: usize,
+    rank: impl Fn(u32, Vec<u32>, &[f64]) -> Vec<u32> + 'static,
+) -> BoxedStrategy<Preferences<u32>> {
+    prop::collection::vec(prop::collection::vec(0.0..1.0f64, n), n)
+        .prop_map(move |samples| Preferences {
+            prefs: (0..n as u32)
+                .zip(samples)
+                .map(|(v, row)| {
+                    let others = (0..n as u32).filter(|&x| x != v).collect();
+                    (v, rank(v, others, &row))
+                })
+                .collect(),
+        })
+        .boxed()
+}
+
+/// Everyone wants the same few items: item `i` is drawn with weight `1 / (i + 1)^exponent`, so
+/// an exponent of 0 gives uniformly random lists and larger exponents concentrate demand on
+/// the first items (a Zipf distribution).
+pub fn popularity(n: usize, exponent: f64) -> BoxedStrategy<Preferences<u32>> {
+    profile(n, move |_, others, row| {
+        weighted_order(others, |x| (x as f64 + 1.0).powf(-exponent), row)
+    })
+}
+
+/// Everyone's ranking is a noisy copy of the same reference ranking `0, 1, 2, ...`, drawn from
+/// the Mallows model with the given dispersion in `[0, 1]`. A dispersion of 0 gives everyone the
+/// reference ranking, and 1 gives uniformly random rankings.
+pub fn mallows(n: usize, dispersion: f64) -> BoxedStrategy<Preferences<u32>> {
+    profile(n, move |_, others, row| {
+        // Repeated insertion: the i-th item of the reference goes j places from the end of the
+        // ranking so far with probability proportional to dispersion^j
+        let mut ranking: Vec<u32> = Vec::with_capacity(others.len());
+        for (i, (x, u)) in others.into_iter().zip(row).enumerate() {
+            let weights: Vec<f64> = (0..=i as i32).map(|j| dispersion.powi(j)).collect();
+            let mut target = u * weights.iter().sum::<f64>();
+            let j = weights
+                .iter()
+                .position(|w| {
+                    target -= w;
+                    target < 0.0
+                })
+                .unwrap_or(0);
+            ranking.insert(i - j, x);
+        }
+        ranking
+    })
+}
+
+/// Items belong to `collections` collections, with item `i` in collection `i % collections`,
+/// and participants are drawn to items from the same collection as their own: those get
+/// `affinity` times the weight of other items.
+pub fn correlated(n: usize, collections: usize, affinity: f64) -> BoxedStrategy<Preferences<u32>> {
+    let collections = collections.max(1) as u32;
+    profile(n, move |v, others, row| {
+        let weight = |x: u32| {
+            if x % collections == v % collections {
+                affinity
+            } else {
+                1.0
+            }
+        };
+        weighted_order(others, weight, row)
+    })
+}
+
+/// Cut every list in `profile` to a random length in `len`, as when people only bother to rank
+/// a handful of items.
+pub fn truncated(
+    profile: BoxedStrategy<Preferences<u32>>,
+    len: RangeInclusive<usize>,
+) -> BoxedStrategy<Preferences<u32>> {
+    profile
+        .prop_flat_map(move |p| {
+            let lens = prop::collection::vec(len.clone(), p.prefs.len());
+            (Just(p), lens)
+        })
+        .prop_map(|(mut p, lens)| {
+            let mut participants: Vec<u32> = p.prefs.keys().cloned().collect();
+            participants.sort();
+            for (v, len) in participants.into_iter().zip(lens) {
+                p.prefs.get_mut(&v).unwrap().truncate(len);
+            }
+            p
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use proptest::{
+        strategy::ValueTree,
+        test_runner::{Config, TestRunner},
+    };
+
+    fn sample(strategy: BoxedStrategy<Preferences<u32>>) -> Preferences<u32> {
+        let mut runner = TestRunner::deterministic();
+        strategy.new_tree(&mut runner).unwrap().current()
+    }
+
+    // How many participants rank `item` first
+    fn first_choices(p: &Preferences<u32>, item: u32) -> usize {
+        p.prefs
+            .values()
+            .filter(|l| l.first() == Some(&item))
+            .count()
+    }
+
+    #[test]
+    fn popularity_test() {
+        let p = sample(popularity(50, 3.0));
+        // Everyone but 0 itself wants 0 with probability around 0.8
+        assert!(first_choices(&p, 0) > 30);
+        let p = sample(popularity(50, 0.0));
+        assert!(first_choices(&p, 0) < 10);
+    }
+
+    #[test]
+    fn mallows_test() {
+        let p = sample(mallows(20, 0.0));
+        for (v, list) in p.prefs.iter() {
+            let reference: Vec<u32> = (0..20).filter(|x| x != v).collect();
+            assert_eq!(list, &reference);
+        }
+        let p = sample(mallows(50, 1.0));
+        assert!(first_choices(&p, 0) < 10);
+    }
+
+    #[test]
+    fn correlated_test() {
+        let p = sample(correlated(50, 5, 1000.0));
+        let same = p
+            .prefs
+            .iter()
+            .filter(|(v, l)| l.first().is_some_and(|x| x % 5 == *v % 5))
+            .count();
+        assert!(same > 45);
+    }
+
+    proptest! {
+    #![proptest_config(Config::with_cases(64))]
+    #[test]
+    fn test_generators_are_valid(
+        (n, len, p) in (2..30usize, 0.0..2.0f64, 0..10usize, 0..3usize).prop_flat_map(|(n, param, len, kind)| {
+            let profile = match kind {
+                0 => popularity(n, param),
+                1 => mallows(n, param.min(1.0)),
+                _ => correlated(n, 3, param + 1.0),
+            };
+            (Just(n), Just(len), truncated(profile, len..=len))
+        }))
+      { prop_assert_eq!(p.prefs.len(), n);
+        prop_assert_eq!(p.validate(), Ok(()));
+        for (v, list) in p.prefs.iter() {
+            prop_assert!(!list.contains(v));
+            prop_assert_eq!(list.len(), len.min(n - 1));
+        }
+      }
+    }
+}
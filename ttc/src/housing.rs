@@ -0,0 +1,415 @@
+use crate::strict::{Allocation, TTCError};
+use alloc::{format, vec, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::{HashMap, HashSet};
+use thiserror::Error;
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+#[derive(Debug, Error)]
+pub enum HousingError<A: Display> {
+    #[error("{} has preferences for houses that don't exist", _0)]
+    InvalidChoice(A),
+    #[error("{} is missing from the priority order, or appears in it twice", _0)]
+    InvalidPriority(A),
+    #[error("{} occupies a house that is vacant or occupied by someone else", _0)]
+    InvalidTenancy(A),
+    #[error("A vacant house is listed twice")]
+    DuplicateVacancy,
+}
+
+/// A housing market with existing tenants: some agents occupy a house, some houses are vacant,
+/// and some agents (newcomers) have no house at all. Houses are identified by `H` and agents by
+/// `A`, since newcomers don't bring a house to be identified with.
+#[derive(Debug, Clone)]
+pub struct Preferences<A, H> {
+    pub prefs: HashMap<A, Vec<H>>,
+    /// The house each existing tenant occupies
+    pub tenants: HashMap<A, H>,
+    pub vacant: Vec<H>,
+    /// Every agent, in the order they get to choose
+    pub priority: Vec<A>,
+}
+
+impl<A, H> Preferences<A, H>
+where
+    A: Display + Eq + Hash + Clone,
+    H: Eq + Hash,
+{
+    pub fn new(
+        prefs: HashMap<A, Vec<H>>,
+        tenants: HashMap<A, H>,
+        vacant: Vec<H>,
+        priority: Vec<A>,
+    ) -> Result<Self, HousingError<A>> {
+        let prefs = Self {
+            prefs,
+            tenants,
+            vacant,
+            priority,
+        };
+        prefs.check()?;
+        Ok(prefs)
+    }
+
+    fn check(&self) -> Result<(), HousingError<A>> {
+        let mut houses: HashSet<&H> = self.vacant.iter().collect();
+        if houses.len() != self.vacant.len() {
+            return Err(HousingError::DuplicateVacancy);
+        }
+        for (tenant, house) in self.tenants.iter() {
+            if !houses.insert(house) {
+                return Err(HousingError::InvalidTenancy(tenant.clone()));
+            }
+        }
+        for (agent, hs) in self.prefs.iter() {
+            if !hs.iter().all(|h| houses.contains(h)) {
+                return Err(HousingError::InvalidChoice(agent.clone()));
+            }
+        }
+        let mut seen = HashSet::new();
+        for agent in self.priority.iter() {
+            if !seen.insert(agent) {
+                return Err(HousingError::InvalidPriority(agent.clone()));
+            }
+        }
+        if let Some(agent) = self
+            .prefs
+            .keys()
+            .chain(self.tenants.keys())
+            .find(|a| !seen.contains(a))
+        {
+            return Err(HousingError::InvalidPriority(agent.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// The "you request my house, I get your turn" mechanism (Abdulkadiroğlu and Sönmez, 1999).
+/// Agents choose in priority order, each taking its favourite remaining house. If that house is
+/// occupied by a tenant who hasn't chosen yet, the tenant jumps the queue and chooses first, and
+/// if that closes a loop of tenants requesting each other's houses, the loop trades like a TTC
+/// cycle. A tenant's house becomes vacant once the tenant moves out, and tenants who find nothing
+/// better keep their house. With no newcomers and no vacancies this is TTC, and with no tenants
+/// it's serial dictatorship.
+///
+/// https://doi.org/10.1006/jeth.1999.2553
+pub struct PreferenceGraph<A, H> {
+    // Agents in priority order
+    agents: Vec<A>,
+    houses: Vec<H>,
+    prefs: Vec<Vec<usize>>,
+    home: Vec<Option<usize>>,
+    occupant: Vec<Option<usize>>,
+}
+
+impl<A, H> PreferenceGraph<A, H>
+where
+    A: Eq + Hash + Clone + Display,
+    H: Eq + Hash + Clone + Display,
+{
+    pub fn new(prefs: Preferences<A, H>) -> Result<Self, TTCError> {
+        let agents = prefs.priority;
+        if agents.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        let houses: Vec<H> = prefs
+            .vacant
+            .into_iter()
+            .chain(prefs.tenants.values().cloned())
+            .collect();
+        let house_index: HashMap<H, usize> = houses
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.clone(), i))
+            .collect();
+        let agent_index: HashMap<A, usize> = agents
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.clone(), i))
+            .collect();
+
+        let mut home = vec![None; agents.len()];
+        let mut occupant = vec![None; houses.len()];
+        for (tenant, house) in prefs.tenants.iter() {
+            let a = *agent_index
+                .get(tenant)
+                .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", tenant, house)))?;
+            home[a] = Some(house_index[house]);
+            occupant[house_index[house]] = Some(a);
+        }
+        let ranked = agents
+            .iter()
+            .map(|a| {
+                prefs
+                    .prefs
+                    .get(a)
+                    .into_iter()
+                    .flatten()
+                    .map(|h| {
+                        house_index
+                            .get(h)
+                            .copied()
+                            .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", a, h)))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, TTCError>>()?;
+
+        Ok(Self {
+            agents,
+            houses,
+            prefs: ranked,
+            home,
+            occupant,
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(&self) -> Result<Allocation<A, H>, TTCError> {
+        let n = self.agents.len();
+        let mut taken = vec![false; self.houses.len()];
+        let mut served = vec![false; n];
+        let mut on_stack = vec![false; n];
+        let mut cursor = vec![0usize; n];
+        let mut allocation = HashMap::new();
+        // Agents waiting for the tenant above them to choose, each having requested that
+        // tenant's house
+        let mut stack: Vec<usize> = Vec::new();
+
+        for next in 0..n {
+            if served[next] {
+                continue;
+            }
+            stack.push(next);
+            on_stack[next] = true;
+            while let Some(&a) = stack.last() {
+                let prefs = &self.prefs[a];
+                while cursor[a] < prefs.len() && taken[prefs[cursor[a]]] {
+                    cursor[a] += 1;
+                }
+                // Tenants can always stay where they are
+                let Some(h) = prefs.get(cursor[a]).copied().or(self.home[a]) else {
+                    // A newcomer that doesn't want any of the remaining houses
+                    stack.pop();
+                    on_stack[a] = false;
+                    served[a] = true;
+                    continue;
+                };
+                match self.occupant[h].filter(|&t| !served[t]) {
+                    Some(t) if !on_stack[t] => {
+                        stack.push(t);
+                        on_stack[t] = true;
+                    }
+                    Some(t) => {
+                        // Everyone from `t` up requested the house of the agent above them, and
+                        // `a` requested `t`'s house
+                        let from = stack
+                            .iter()
+                            .rposition(|&x| x == t)
+                            .ok_or(TTCError::AlwaysCycles)?;
+                        let cycle = stack.split_off(from);
+                        let requests = cycle.iter().skip(1).map(|&x| self.home[x]).chain([Some(h)]);
+                        for (&x, house) in cycle.iter().zip(requests) {
+                            let house = house.ok_or(TTCError::AlwaysCycles)?;
+                            taken[house] = true;
+                            served[x] = true;
+                            on_stack[x] = false;
+                            allocation.insert(self.agents[x].clone(), self.houses[house].clone());
+                        }
+                    }
+                    None => {
+                        stack.pop();
+                        on_stack[a] = false;
+                        served[a] = true;
+                        taken[h] = true;
+                        allocation.insert(self.agents[a].clone(), self.houses[h].clone());
+                    }
+                }
+            }
+        }
+        Ok(Allocation { allocation })
+    }
+}
+
+#[cfg(any(test, feature = "test"))]
+pub mod test_utils {
+    use super::*;
+    use crate::test_utils;
+    use proptest::prelude::*;
+
+    impl<V> Arbitrary for Preferences<V, V>
+    where
+        V: Clone + Eq + Hash + core::fmt::Debug + Arbitrary + 'static,
+        V::Strategy: 'static,
+    {
+        type Parameters = Option<core::ops::RangeInclusive<usize>>;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(params: Self::Parameters) -> Self::Strategy {
+            test_utils::strict_with(params, |prefs| {
+                let len = prefs.prefs.len();
+                let is_tenant = prop::collection::vec(any::<bool>(), len);
+                let newcomers = prop::collection::vec(
+                    (any::<V>(), prop::collection::vec(0..len, 0..=len)),
+                    0..=len,
+                );
+                (is_tenant, newcomers)
+            })
+            .prop_flat_map(|(prefs, (is_tenant, newcomers))| {
+                let houses: Vec<V> = prefs.prefs.keys().cloned().collect();
+                let mut agents_prefs: HashMap<V, Vec<V>> = HashMap::new();
+                let mut tenants = HashMap::new();
+                let mut vacant = vec![];
+                // A house is either vacant or occupied by the agent with the same name
+                for (house, tenant) in houses.iter().zip(is_tenant) {
+                    if tenant {
+                        tenants.insert(house.clone(), house.clone());
+                        agents_prefs.insert(house.clone(), prefs.prefs[house].clone());
+                    } else {
+                        vacant.push(house.clone());
+                    }
+                }
+                for (agent, choices) in newcomers {
+                    if houses.contains(&agent) || agents_prefs.contains_key(&agent) {
+                        continue;
+                    }
+                    agents_prefs.insert(agent, test_utils::ranking(choices, &houses));
+                }
+                let agents: Vec<V> = agents_prefs.keys().cloned().collect();
+                (
+                    Just(agents_prefs),
+                    Just(tenants),
+                    Just(vacant),
+                    Just(agents).prop_shuffle(),
+                )
+            })
+            .prop_map(|(prefs, tenants, vacant, priority)| Preferences {
+                prefs,
+                tenants,
+                vacant,
+                priority,
+            })
+            .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::{self, Allocation};
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn basic_test() {
+        // "a" lives in 1 and "b" in 2, 3 is vacant, and "c" is new
+        let prefs = vec![("a", vec![2, 3]), ("b", vec![1]), ("c", vec![1, 3])];
+        let tenants = vec![("a", 1), ("b", 2)];
+        let prefs = Preferences::new(
+            prefs.into_iter().collect(),
+            tenants.into_iter().collect(),
+            vec![3],
+            vec!["c", "b", "a"],
+        )
+        .unwrap();
+        let alloc = PreferenceGraph::new(prefs)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        // "c" requests 1, so "a" chooses first and requests 2, so "b" chooses first and requests
+        // 1: "a" and "b" swap, and "c" gets 3
+        assert_eq!(
+            alloc.allocation,
+            vec![("a", 2), ("b", 1), ("c", 3)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn validation_test() {
+        let prefs: HashMap<&str, Vec<u32>> =
+            vec![("a", vec![2]), ("b", vec![])].into_iter().collect();
+        let tenants: HashMap<&str, u32> = vec![("a", 1)].into_iter().collect();
+        assert!(matches!(
+            Preferences::new(prefs.clone(), tenants.clone(), vec![3], vec!["a", "b"]),
+            Err(HousingError::InvalidChoice("a"))
+        ));
+        assert!(matches!(
+            Preferences::new(prefs.clone(), tenants.clone(), vec![2], vec!["a"]),
+            Err(HousingError::InvalidPriority("b"))
+        ));
+        assert!(matches!(
+            Preferences::new(prefs, tenants, vec![1, 2], vec!["a", "b"]),
+            Err(HousingError::InvalidTenancy("a"))
+        ));
+    }
+
+    // Each agent in priority order takes its favourite remaining house
+    fn serial_dictatorship(p: &Preferences<u32, u32>) -> Allocation<u32> {
+        let mut taken = HashSet::new();
+        let mut allocation = HashMap::new();
+        for agent in &p.priority {
+            if let Some(h) = p.prefs[agent].iter().find(|h| !taken.contains(*h)) {
+                taken.insert(*h);
+                allocation.insert(*agent, *h);
+            }
+        }
+        Allocation { allocation }
+    }
+
+    proptest! {
+    #[test]
+    fn test_can_solve_random_market(p in Preferences::<u32, u32>::arbitrary())
+      { prop_assume!(!p.priority.is_empty());
+        let alloc = PreferenceGraph::new(p.clone()).unwrap().solve_preferences().unwrap();
+
+        // Check that no house is allocated twice, and only houses in the market are allocated
+        let houses: HashSet<u32> = p.vacant.iter().chain(p.tenants.values()).cloned().collect();
+        let allocated: Vec<u32> = alloc.allocation.values().cloned().collect();
+        prop_assert_eq!(allocated.iter().collect::<HashSet<_>>().len(), allocated.len());
+        prop_assert!(allocated.iter().all(|h| houses.contains(h)));
+
+        // Check that tenants do at least as well as their current house, and everyone gets a house
+        // they ranked (or their own)
+        for (agent, house) in &alloc.allocation {
+            let list = &p.prefs[agent];
+            let rank = |h: &u32| list.iter().position(|x| x == h).unwrap_or(list.len());
+            match p.tenants.get(agent) {
+                Some(home) => prop_assert!(rank(house) <= rank(home)),
+                None => prop_assert!(list.contains(house)),
+            }
+        }
+        for tenant in p.tenants.keys() {
+            prop_assert!(alloc.allocation.contains_key(tenant), "{} was evicted", tenant);
+        }
+      }
+
+    #[test]
+    fn test_matches_ttc_without_newcomers_or_vacancies(
+        (p, priority) in strict::Preferences::<u32>::arbitrary().prop_flat_map(|p| {
+            let agents: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), Just(agents).prop_shuffle())
+        }))
+      { let tenants = p.prefs.keys().map(|&v| (v, v)).collect();
+        let market = Preferences::new(p.prefs.clone(), tenants, vec![], priority).unwrap();
+        let alloc = PreferenceGraph::new(market).unwrap().solve_preferences().unwrap();
+        let ttc = strict::PreferenceGraph::new(p).unwrap().solve_preferences().unwrap();
+        prop_assert_eq!(alloc.allocation, Allocation::from(ttc).allocation);
+      }
+
+    #[test]
+    fn test_matches_serial_dictatorship_without_tenants(p in Preferences::<u32, u32>::arbitrary())
+      { let p = Preferences {
+            prefs: p.prefs.into_iter().filter(|(a, _)| !p.tenants.contains_key(a)).collect(),
+            priority: p.priority.into_iter().filter(|a| !p.tenants.contains_key(a)).collect(),
+            vacant: p.vacant.into_iter().chain(p.tenants.into_values()).collect(),
+            tenants: HashMap::new(),
+        };
+        prop_assume!(!p.priority.is_empty());
+        let alloc = PreferenceGraph::new(p.clone()).unwrap().solve_preferences().unwrap();
+        prop_assert_eq!(alloc.allocation, serial_dictatorship(&p).allocation);
+      }
+    }
+}
@@ -0,0 +1,194 @@
+//! Reading and writing preference profiles, so that a pool's preferences can be saved or a
+//! hand-written test case loaded.
+//!
+//! The JSON format maps every participant to its preference list, e.g. `{"1": [2, 3], "2": []}`.
+//! In the CSV format every row is a participant followed by its preference list, so rows have
+//! different lengths and there is no header.
+use crate::strict::Preferences;
+use hashbrown::HashMap;
+use serde::{
+    de::{DeserializeOwned, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{
+    fmt::{self, Display},
+    hash::Hash,
+    io::{Read, Write},
+    marker::PhantomData,
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FormatError {
+    #[error("JSON error: {}", _0)]
+    Json(#[from] serde_json::Error),
+    #[error("CSV error: {}", _0)]
+    Csv(#[from] csv::Error),
+    #[error("Row {} is empty", _0)]
+    EmptyRow(usize),
+    #[error("Participant {} appears more than once", _0)]
+    DuplicateParticipant(String),
+}
+
+// The JSON object's entries in order, so that a repeated participant can be caught instead of
+// the last entry silently winning
+struct Entries<V>(Vec<(V, Vec<V>)>);
+
+impl<'de, V: Deserialize<'de>> Deserialize<'de> for Entries<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<V>(PhantomData<V>);
+
+        impl<'de, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<V> {
+            type Value = Entries<V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map from participants to preference lists")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Self::Value, M::Error> {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Entries(entries))
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor(PhantomData))
+    }
+}
+
+pub fn read_json<V, R>(reader: R) -> Result<Preferences<V>, FormatError>
+where
+    V: Eq + Hash + Display + DeserializeOwned,
+    R: Read,
+{
+    let Entries(entries): Entries<V> = serde_json::from_reader(reader)?;
+    let mut prefs = HashMap::with_capacity(entries.len());
+    for (participant, vs) in entries {
+        if prefs.contains_key(&participant) {
+            return Err(FormatError::DuplicateParticipant(participant.to_string()));
+        }
+        prefs.insert(participant, vs);
+    }
+    Ok(Preferences { prefs })
+}
+
+pub fn write_json<V, W>(prefs: &Preferences<V>, writer: W) -> Result<(), FormatError>
+where
+    V: Eq + Hash + Serialize,
+    W: Write,
+{
+    Ok(serde_json::to_writer_pretty(writer, prefs)?)
+}
+
+pub fn read_csv<V, R>(reader: R) -> Result<Preferences<V>, FormatError>
+where
+    V: Eq + Hash + Display + DeserializeOwned,
+    R: Read,
+{
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+    let mut prefs = HashMap::new();
+    for (row, record) in reader.deserialize::<Vec<V>>().enumerate() {
+        let mut record = record?.into_iter();
+        let participant = record.next().ok_or(FormatError::EmptyRow(row))?;
+        if prefs.contains_key(&participant) {
+            return Err(FormatError::DuplicateParticipant(participant.to_string()));
+        }
+        prefs.insert(participant, record.collect());
+    }
+    Ok(Preferences { prefs })
+}
+
+/// Write the profile as CSV. Rows are sorted by participant so that the same profile always
+/// produces the same file.
+pub fn write_csv<V, W>(prefs: &Preferences<V>, writer: W) -> Result<(), FormatError>
+where
+    V: Ord + Serialize,
+    W: Write,
+{
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_writer(writer);
+    let mut rows: Vec<(&V, &Vec<V>)> = prefs.prefs.iter().collect();
+    rows.sort_by(|a, b| a.0.cmp(b.0));
+    for (participant, vs) in rows {
+        writer.serialize((participant, vs))?;
+    }
+    writer.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::{Allocation, Cycle, PreferenceGraph};
+    use proptest::prelude::*;
+
+    #[test]
+    fn reads_hand_written_profiles() {
+        let json = r#"{ "alice": ["bob", "carol"], "bob": ["alice"], "carol": [] }"#;
+        let csv = "alice,bob,carol\nbob,alice\ncarol\n";
+        let from_json: Preferences<String> = read_json(json.as_bytes()).unwrap();
+        let from_csv: Preferences<String> = read_csv(csv.as_bytes()).unwrap();
+        assert_eq!(from_json.prefs, from_csv.prefs);
+        assert_eq!(
+            from_csv.get(&"alice".to_string()),
+            Some(&vec!["bob".to_string(), "carol".to_string()])
+        );
+
+        let mut out = vec![];
+        write_csv(&from_json, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), csv);
+
+        assert!(matches!(
+            read_csv::<String, _>("a,b\nb\na\n".as_bytes()),
+            Err(FormatError::DuplicateParticipant(p)) if p == "a"
+        ));
+        assert!(matches!(
+            read_json::<String, _>(r#"{ "a": ["b"], "b": [], "a": [] }"#.as_bytes()),
+            Err(FormatError::DuplicateParticipant(p)) if p == "a"
+        ));
+    }
+
+    #[test]
+    fn serializes_solutions() {
+        let prefs = vec![(1, vec![2]), (2, vec![1]), (3, vec![])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let cycles = PreferenceGraph::new(prefs)
+            .unwrap()
+            .solve_canonical()
+            .unwrap();
+        assert_eq!(serde_json::to_string(&cycles).unwrap(), "[[1,2],[3]]");
+        let decoded: Vec<Cycle<u32>> = serde_json::from_str("[[2,1],[3]]").unwrap();
+        assert_eq!(decoded, cycles);
+
+        let alloc = Allocation::from(cycles);
+        let json = serde_json::to_string(&alloc).unwrap();
+        let decoded: Allocation<u32> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.allocation, alloc.allocation);
+    }
+
+    proptest! {
+    #[test]
+    fn test_json_round_trip(p in Preferences::<u32>::arbitrary())
+      { let mut buf = vec![];
+        write_json(&p, &mut buf).unwrap();
+        let decoded: Preferences<u32> = read_json(buf.as_slice()).unwrap();
+        prop_assert_eq!(decoded.prefs, p.prefs);
+      }
+
+    #[test]
+    fn test_csv_round_trip(p in Preferences::<u32>::arbitrary())
+      { let mut buf = vec![];
+        write_csv(&p, &mut buf).unwrap();
+        let decoded: Preferences<u32> = read_csv(buf.as_slice()).unwrap();
+        prop_assert_eq!(decoded.prefs, p.prefs);
+      }
+    }
+}
@@ -1 +1,30 @@
+//! Solvers for housing markets, such as the top trading cycle algorithm.
+//!
+//! Without the default `std` feature the crate only needs `alloc`, which keeps the zkVM guest
+//! lean. The `weak` and `verify` modules need `std`. The `tracing` feature (also on by default)
+//! instruments the solvers.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod analysis;
+pub mod bounded;
+pub mod dot;
+#[cfg(any(test, feature = "test"))]
+pub mod generators;
+pub mod housing;
+#[cfg(feature = "io")]
+pub mod io;
+pub mod mechanism;
+pub mod multi;
+pub mod school;
+pub mod stats;
 pub mod strict;
+#[cfg(any(test, feature = "test"))]
+mod test_utils;
+pub mod trace;
+pub mod ttcc;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "std")]
+pub mod weak;
@@ -0,0 +1,255 @@
+use crate::{
+    bounded,
+    strict::{Allocation, PreferenceGraph, Preferences, TTCError},
+};
+use alloc::{format, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::{HashMap, HashSet};
+
+/// An allocation rule for housing markets: a map from everyone's reported preferences to who
+/// gets which item. Analyses that only care about inputs and outputs (see
+/// [`crate::analysis`]) are written against this trait so that they apply to every mechanism.
+pub trait Mechanism<V> {
+    type Error: core::error::Error;
+
+    fn allocate(&self, prefs: &Preferences<V>) -> Result<Allocation<V>, Self::Error>;
+}
+
+/// Gale's top trading cycle, as solved by [`PreferenceGraph`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TopTradingCycle;
+
+impl<V> Mechanism<V> for TopTradingCycle
+where
+    V: Clone + Eq + Hash,
+{
+    type Error = TTCError;
+
+    fn allocate(&self, prefs: &Preferences<V>) -> Result<Allocation<V>, TTCError> {
+        let cycles = PreferenceGraph::new(prefs.clone())?.solve_preferences()?;
+        Ok(Allocation::from(cycles))
+    }
+}
+
+/// Exchange using only cycles of at most `max_len` participants, as solved by
+/// [`bounded::PreferenceGraph`].
+#[derive(Debug, Clone, Copy)]
+pub struct BoundedExchange {
+    pub max_len: usize,
+}
+
+impl<V> Mechanism<V> for BoundedExchange
+where
+    V: Ord + Display + Hash + Copy,
+{
+    type Error = TTCError;
+
+    fn allocate(&self, prefs: &Preferences<V>) -> Result<Allocation<V>, TTCError> {
+        let cycles =
+            bounded::PreferenceGraph::new(prefs.clone(), self.max_len)?.solve_preferences()?;
+        Ok(Allocation::from(cycles))
+    }
+}
+
+/// Participants choose one at a time in `priority` order, each taking its most preferred item
+/// that's still available, or its own item if none of its choices are left. Participants missing
+/// from `priority` choose last, in their natural order. Anyone whose own item was taken and who
+/// has nothing left to choose from gets one of the leftover items, smallest first, so a
+/// participant can receive an item they didn't rank.
+///
+/// Unlike TTC this ignores who deposited what, so it isn't individually rational, but it is
+/// strategy-proof and Pareto efficient.
+#[derive(Debug, Clone, Default)]
+pub struct SerialDictatorship<V> {
+    pub priority: Vec<V>,
+}
+
+impl<V> SerialDictatorship<V>
+where
+    V: Ord + Display + Hash + Clone,
+{
+    fn order(&self, prefs: &Preferences<V>) -> Vec<V> {
+        let mut seen = HashSet::new();
+        let mut order: Vec<V> = self
+            .priority
+            .iter()
+            .filter(|v| prefs.prefs.contains_key(*v) && seen.insert(*v))
+            .cloned()
+            .collect();
+        let mut rest: Vec<&V> = prefs.prefs.keys().filter(|v| !seen.contains(v)).collect();
+        rest.sort();
+        order.extend(rest.into_iter().cloned());
+        order
+    }
+}
+
+impl<V> Mechanism<V> for SerialDictatorship<V>
+where
+    V: Ord + Display + Hash + Clone,
+{
+    type Error = TTCError;
+
+    fn allocate(&self, prefs: &Preferences<V>) -> Result<Allocation<V>, TTCError> {
+        if prefs.prefs.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        let mut allocation = HashMap::new();
+        let mut taken = HashSet::new();
+        let mut empty_handed = Vec::new();
+        for v in self.order(prefs) {
+            let list = &prefs.prefs[&v];
+            if let Some(x) = list.iter().find(|x| !prefs.prefs.contains_key(*x)) {
+                return Err(TTCError::InvalidEdge(format!("{} -> {}", v, x)));
+            }
+            match list
+                .iter()
+                .chain(core::iter::once(&v))
+                .find(|x| !taken.contains(*x))
+                .cloned()
+            {
+                Some(x) => {
+                    taken.insert(x.clone());
+                    allocation.insert(v, x);
+                }
+                None => empty_handed.push(v),
+            }
+        }
+        let mut leftover: Vec<&V> = prefs.prefs.keys().filter(|v| !taken.contains(*v)).collect();
+        leftover.sort();
+        for (v, x) in empty_handed.into_iter().zip(leftover) {
+            allocation.insert(v, x.clone());
+        }
+        Ok(Allocation { allocation })
+    }
+}
+
+/// [`SerialDictatorship`] with the priority order drawn uniformly at random from `seed`. The same
+/// seed and participants always give the same order, so committing to the seed before preferences
+/// are revealed makes the draw verifiable.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomSerialDictatorship {
+    pub seed: u64,
+}
+
+impl RandomSerialDictatorship {
+    /// Shuffle the participants, sorted, with Fisher-Yates driven by SplitMix64.
+    pub fn priority<V: Ord + Clone>(&self, participants: &[V]) -> Vec<V> {
+        let mut order = participants.to_vec();
+        order.sort();
+        let mut state = self.seed;
+        let mut next = || {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        for i in (1..order.len()).rev() {
+            let j = (next() % (i as u64 + 1)) as usize;
+            order.swap(i, j);
+        }
+        order
+    }
+}
+
+impl<V> Mechanism<V> for RandomSerialDictatorship
+where
+    V: Ord + Display + Hash + Clone,
+{
+    type Error = TTCError;
+
+    fn allocate(&self, prefs: &Preferences<V>) -> Result<Allocation<V>, TTCError> {
+        let participants: Vec<V> = prefs.prefs.keys().cloned().collect();
+        SerialDictatorship {
+            priority: self.priority(&participants),
+        }
+        .allocate(prefs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::analysis::explore_manipulations;
+    use proptest::prelude::*;
+
+    #[test]
+    fn serial_dictatorship_test() {
+        let prefs = vec![(1, vec![3, 2]), (2, vec![3]), (3, vec![1])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let alloc = SerialDictatorship {
+            priority: vec![2, 1],
+        }
+        .allocate(&prefs)
+        .unwrap();
+        // 2 takes 3, 1 takes 2, and 3 takes 1
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 2), (2, 3), (3, 1)].into_iter().collect()
+        );
+
+        let prefs = vec![(1, vec![2]), (2, vec![1]), (3, vec![])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let alloc = SerialDictatorship {
+            priority: vec![3, 2, 1],
+        }
+        .allocate(&prefs)
+        .unwrap();
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 2), (2, 1), (3, 3)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn serial_dictatorship_leftover_test() {
+        let prefs = vec![(1, vec![2]), (2, vec![1]), (3, vec![1])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let alloc = SerialDictatorship {
+            priority: vec![1, 3, 2],
+        }
+        .allocate(&prefs)
+        .unwrap();
+        // 1 takes 2 and 3 takes 1, so 2 is left with 3's item, which it didn't rank
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 2), (2, 3), (3, 1)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn random_priority_is_deterministic() {
+        let rsd = RandomSerialDictatorship { seed: 42 };
+        let order = rsd.priority(&[5, 1, 4, 2, 3]);
+        assert_eq!(order, rsd.priority(&[1, 2, 3, 4, 5]));
+        let mut sorted = order.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![1, 2, 3, 4, 5]);
+    }
+
+    proptest! {
+    #[test]
+    fn test_serial_dictatorship_is_a_permutation(p in Preferences::<u32>::arbitrary(), seed in any::<u64>())
+      { let alloc = RandomSerialDictatorship { seed }.allocate(&p).unwrap();
+        let mut received: Vec<u32> = alloc.allocation.values().cloned().collect();
+        let mut participants: Vec<u32> = p.prefs.keys().cloned().collect();
+        received.sort();
+        participants.sort();
+        prop_assert_eq!(alloc.allocation.len(), participants.len());
+        prop_assert_eq!(received, participants);
+      }
+
+    #[test]
+    fn test_serial_dictatorship_is_strategy_proof(
+        (p, participant) in Preferences::<u32>::arbitrary_with(Some(2..=6)).prop_flat_map(|p| {
+            let participants: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), prop::sample::select(participants))
+        }),
+        seed in any::<u64>())
+      { let rsd = RandomSerialDictatorship { seed };
+        let report = explore_manipulations(&rsd, &p, &participant, 2).unwrap();
+        prop_assert!(report.truthful_is_optimal(), "{:?}", report.counterexamples);
+      }
+    }
+}
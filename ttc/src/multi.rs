@@ -0,0 +1,354 @@
+use crate::strict::{self, Cycle, PrefsError, TTCError};
+use alloc::{string::ToString, vec, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::HashMap;
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+/// Preferences for a pool where a single owner may deposit several items. Each owner submits one
+/// ranking over the deposited items, and receives back as many items as they deposited.
+#[derive(Debug, Clone)]
+pub struct Preferences<A, V> {
+    pub owners: HashMap<V, A>,
+    pub prefs: HashMap<A, Vec<V>>,
+}
+
+impl<A, V> Preferences<A, V> {
+    pub fn participants(&self) -> Vec<&A> {
+        self.prefs.keys().collect()
+    }
+}
+
+impl<A: Eq + Hash, V: Eq + Hash> Preferences<A, V> {
+    pub fn get(&self, a: &A) -> Option<&Vec<V>> {
+        self.prefs.get(a)
+    }
+
+    pub fn endowment(&self, a: &A) -> Vec<&V> {
+        self.owners
+            .iter()
+            .filter(|(_, owner)| *owner == a)
+            .map(|(v, _)| v)
+            .collect()
+    }
+}
+
+impl<A, V> Preferences<A, V>
+where
+    A: Display + Eq + Hash + Clone,
+    V: Eq + Hash + Clone + Ord,
+{
+    pub fn new(owners: HashMap<V, A>, prefs: HashMap<A, Vec<V>>) -> Result<Self, PrefsError<A>> {
+        for (k, vs) in prefs.iter() {
+            if !vs.iter().all(|a| owners.contains_key(a)) {
+                return Err(PrefsError::InvalidChoice(k.clone()));
+            }
+        }
+        Ok(Self { owners, prefs })
+    }
+
+    /// Merge per-item rankings into a single ranking per owner. The lists for an owner's items are
+    /// interleaved position by position (in item order), so that each item's first choice is
+    /// considered before any item's second choice. Each list is cut after its own item, since
+    /// anything ranked below it was never acceptable in exchange for that item.
+    pub fn from_token_preferences(
+        owners: HashMap<V, A>,
+        prefs: strict::Preferences<V>,
+    ) -> Result<Self, PrefsError<A>> {
+        let mut endowments: HashMap<&A, Vec<&V>> = HashMap::new();
+        for (v, a) in owners.iter() {
+            endowments.entry(a).or_default().push(v);
+        }
+
+        let merged = endowments
+            .into_iter()
+            .map(|(a, mut vs)| {
+                vs.sort();
+                let lists: Vec<&[V]> = vs
+                    .iter()
+                    .map(|&v| {
+                        let list = prefs.get(v).map(|l| l.as_slice()).unwrap_or_default();
+                        match list.iter().position(|x| x == v) {
+                            Some(ix) => &list[..=ix],
+                            None => list,
+                        }
+                    })
+                    .collect();
+                let longest = lists.iter().map(|l| l.len()).max().unwrap_or_default();
+                let mut ranking: Vec<V> = Vec::new();
+                for position in 0..longest {
+                    for choice in lists.iter().filter_map(|l| l.get(position)) {
+                        if !ranking.contains(choice) {
+                            ranking.push(choice.clone());
+                        }
+                    }
+                }
+                (a.clone(), ranking)
+            })
+            .collect();
+
+        Self::new(owners, merged)
+    }
+}
+
+/// Top trading cycles where owners may hold several items. Each owner points at the owner of their
+/// most preferred remaining item, and every cycle moves one item to each owner in it. An owner
+/// stays in the market until they have received as many items as they deposited.
+///
+/// The solution is returned as cycles of items in the same format as [`strict::PreferenceGraph`]:
+/// the owner of each item in a cycle receives the next item.
+pub struct PreferenceGraph<A, V> {
+    owners: Vec<A>,
+    items: Vec<V>,
+    owner_of: Vec<usize>,
+    // For each owner, their ranking followed by any of their own items they didn't rank
+    prefs: Vec<Vec<usize>>,
+}
+
+impl<A, V> PreferenceGraph<A, V>
+where
+    A: Eq + Hash + Clone + Ord,
+    V: Eq + Hash + Clone + Ord + Display,
+{
+    pub fn new(prefs: Preferences<A, V>) -> Result<Self, TTCError> {
+        let mut items: Vec<V> = prefs.owners.keys().cloned().collect();
+        if items.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        items.sort();
+        let mut owners: Vec<A> = prefs.owners.values().cloned().collect();
+        owners.sort();
+        owners.dedup();
+
+        let item_index: HashMap<V, usize> = items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), i))
+            .collect();
+        let owner_index: HashMap<A, usize> = owners
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.clone(), i))
+            .collect();
+        let owner_of: Vec<usize> = items
+            .iter()
+            .map(|v| owner_index[&prefs.owners[v]])
+            .collect();
+
+        let ranked: Vec<Vec<usize>> = owners
+            .iter()
+            .enumerate()
+            .map(|(me, a)| {
+                let mut ranking = Vec::new();
+                for v in prefs.prefs.get(a).into_iter().flatten() {
+                    let ix = *item_index
+                        .get(v)
+                        .ok_or_else(|| TTCError::InvalidEdge(v.to_string()))?;
+                    if !ranking.contains(&ix) {
+                        ranking.push(ix);
+                    }
+                }
+                for (ix, &owner) in owner_of.iter().enumerate() {
+                    if owner == me && !ranking.contains(&ix) {
+                        ranking.push(ix);
+                    }
+                }
+                Ok(ranking)
+            })
+            .collect::<Result<_, TTCError>>()?;
+
+        Ok(Self {
+            owners,
+            items,
+            owner_of,
+            prefs: ranked,
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(&self) -> Result<Vec<Cycle<V>>, TTCError> {
+        let mut removed = vec![false; self.items.len()];
+        let mut remaining = vec![0usize; self.owners.len()];
+        self.owner_of.iter().for_each(|&a| remaining[a] += 1);
+        let mut cursor = vec![0usize; self.owners.len()];
+        let mut on_path = vec![false; self.owners.len()];
+
+        let mut res = Vec::new();
+        for start in 0..self.owners.len() {
+            let mut path: Vec<(usize, usize)> = Vec::new();
+            let mut current = start;
+            while remaining[start] > 0 {
+                // Point at the best remaining item; every owner ranks their own items last, so
+                // there is always one while they still hold something.
+                while removed[self.prefs[current][cursor[current]]] {
+                    cursor[current] += 1;
+                }
+                let item = self.prefs[current][cursor[current]];
+                let next = self.owner_of[item];
+                path.push((current, item));
+                on_path[current] = true;
+
+                if !on_path[next] {
+                    current = next;
+                    continue;
+                }
+
+                let from = path.iter().position(|&(a, _)| a == next).unwrap();
+                let cycle: Vec<(usize, usize)> = path.drain(from..).collect();
+                for &(a, item) in &cycle {
+                    on_path[a] = false;
+                    removed[item] = true;
+                    remaining[self.owner_of[item]] -= 1;
+                }
+                res.push(Cycle {
+                    values: cycle
+                        .into_iter()
+                        .map(|(_, item)| self.items[item].clone())
+                        .collect(),
+                });
+
+                // Resume from whoever was pointing into the cycle
+                match path.pop() {
+                    Some((a, _)) => {
+                        on_path[a] = false;
+                        current = a;
+                    }
+                    None => current = start,
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(any(test, feature = "test"))]
+pub mod test_utils {
+    use super::*;
+    use crate::test_utils;
+    use proptest::prelude::*;
+
+    impl<A, V> Arbitrary for Preferences<A, V>
+    where
+        A: Clone + Eq + Hash + Display + std::fmt::Debug + Arbitrary + 'static,
+        V: Clone + Eq + Hash + Ord + std::fmt::Debug + Arbitrary + 'static,
+        A::Strategy: 'static,
+        V::Strategy: 'static,
+    {
+        type Parameters = Option<std::ops::RangeInclusive<usize>>;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(params: Self::Parameters) -> Self::Strategy {
+            test_utils::strict_with(params, |prefs| {
+                let len = prefs.prefs.len();
+                // use fewer owners than items so that most owners hold several items
+                let owners = prop::collection::hash_set(any::<A>(), 1..=len.div_ceil(2));
+                let assignment = prop::collection::vec(any::<prop::sample::Index>(), len);
+                (owners, assignment)
+            })
+            .prop_map(|(prefs, (owners, assignment))| {
+                let owners: Vec<A> = owners.into_iter().collect();
+                let mut items: Vec<V> = prefs.prefs.keys().cloned().collect();
+                items.sort();
+                let owners = items
+                    .into_iter()
+                    .zip(assignment)
+                    .map(|(v, ix)| (v, ix.get(&owners).clone()))
+                    .collect();
+                Preferences::from_token_preferences(owners, prefs).unwrap()
+            })
+            .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::Allocation;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn basic_test() {
+        // "a" deposits 1 and 2, "b" deposits 3 and "c" deposits 4
+        let owners = vec![(1, "a"), (2, "a"), (3, "b"), (4, "c")];
+        let prefs = vec![("a", vec![3, 4]), ("b", vec![1, 2]), ("c", vec![3, 2])];
+        let prefs =
+            Preferences::new(owners.into_iter().collect(), prefs.into_iter().collect()).unwrap();
+        let g = PreferenceGraph::new(prefs).unwrap();
+        let alloc = Allocation::from(g.solve_preferences().unwrap());
+        // "a" swaps 1 for 3 with "b", then 2 for 4 with "c"
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 3), (3, 1), (2, 4), (4, 2)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn merges_token_preferences() {
+        let owners = vec![(1, "a"), (2, "a"), (3, "b"), (4, "b")];
+        let prefs = vec![
+            (1, vec![3, 1, 4]),
+            (2, vec![4, 3]),
+            (3, vec![]),
+            (4, vec![1]),
+        ];
+        let prefs = strict::Preferences::new(prefs.into_iter().collect()).unwrap();
+        let merged =
+            Preferences::from_token_preferences(owners.into_iter().collect(), prefs).unwrap();
+        assert_eq!(merged.get(&"a"), Some(&vec![3, 4, 1]));
+        assert_eq!(merged.get(&"b"), Some(&vec![1]));
+    }
+
+    proptest! {
+    #[test]
+    fn test_can_solve_random_graph(p in Preferences::<u8, u32>::arbitrary())
+      { let g = PreferenceGraph::new(p.clone()).unwrap();
+        let solution = g.solve_preferences();
+        prop_assert!(solution.is_ok(), "Unsolvable graph");
+        let cycles = solution.unwrap();
+
+        // Check that the cycles are disjoint and account for every item
+        {
+            let items: Vec<u32> = cycles.iter().flat_map(|c| c.values.clone()).collect();
+            let unique: HashSet<u32> = items.iter().cloned().collect();
+            let deposited: HashSet<u32> = p.owners.keys().cloned().collect();
+            prop_assert_eq!(items.len(), unique.len(), "Cycles intersect");
+            prop_assert_eq!(unique, deposited, "Not all items were reallocated");
+        }
+
+        let alloc = Allocation::from(cycles);
+
+        // Check that each owner gets back as many items as they deposited, all of which are
+        // either their own or on their list
+        let mut received: HashMap<u8, usize> = HashMap::new();
+        alloc.allocation.iter().try_for_each(|(given, got)| {
+            let owner = p.owners[given];
+            *received.entry(owner).or_default() += 1;
+            if p.owners[got] != owner {
+              prop_assert!(p.prefs[&owner].contains(got), "Preferences for {:?} don't contain {:?}", owner, got);
+            }
+            Ok(())
+        })?;
+        for (owner, count) in received {
+            prop_assert_eq!(p.endowment(&owner).len(), count);
+        }
+      }
+
+    #[test]
+    fn test_agrees_with_strict_preferences(p in strict::Preferences::<u32>::arbitrary())
+      { let strict_alloc = {
+            let mut g = strict::PreferenceGraph::new(p.clone()).unwrap();
+            Allocation::from(g.solve_preferences().unwrap())
+        };
+        let multi_alloc = {
+            let owners = p.prefs.keys().map(|&v| (v, v)).collect();
+            let prefs = Preferences::from_token_preferences(owners, p).unwrap();
+            let g = PreferenceGraph::new(prefs).unwrap();
+            Allocation::from(g.solve_preferences().unwrap())
+        };
+        prop_assert_eq!(strict_alloc.allocation, multi_alloc.allocation);
+      }
+    }
+}
@@ -0,0 +1,483 @@
+use crate::strict::{Allocation, TTCError};
+use alloc::{format, vec, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::{HashMap, HashSet};
+use thiserror::Error;
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+#[derive(Debug, Error)]
+pub enum SchoolError<A: Display, S: Display> {
+    #[error("{} applies to a school that doesn't exist", _0)]
+    InvalidChoice(A),
+    #[error(
+        "{} gives priority to a student that doesn't exist, or to one student twice",
+        _0
+    )]
+    InvalidPriority(S),
+}
+
+/// A school's seats and who it would rather admit.
+#[derive(Debug, Clone)]
+pub struct School<A> {
+    pub capacity: usize,
+    /// Students in the order the school admits them. Students that aren't listed are
+    /// ineligible for the school.
+    pub priority: Vec<A>,
+}
+
+/// A school choice problem: students rank schools, and every school has a number of seats and
+/// a priority order over students. Unlike a housing market nobody owns anything up front, the
+/// schools' priorities play that role instead.
+#[derive(Debug, Clone)]
+pub struct Preferences<A, S> {
+    pub prefs: HashMap<A, Vec<S>>,
+    pub schools: HashMap<S, School<A>>,
+}
+
+impl<A, S> Preferences<A, S>
+where
+    A: Display + Eq + Hash + Clone,
+    S: Display + Eq + Hash + Clone,
+{
+    pub fn new(
+        prefs: HashMap<A, Vec<S>>,
+        schools: HashMap<S, School<A>>,
+    ) -> Result<Self, SchoolError<A, S>> {
+        for (student, choices) in prefs.iter() {
+            if !choices.iter().all(|s| schools.contains_key(s)) {
+                return Err(SchoolError::InvalidChoice(student.clone()));
+            }
+        }
+        for (name, school) in schools.iter() {
+            let mut seen = HashSet::new();
+            if !school
+                .priority
+                .iter()
+                .all(|a| prefs.contains_key(a) && seen.insert(a))
+            {
+                return Err(SchoolError::InvalidPriority(name.clone()));
+            }
+        }
+        Ok(Self { prefs, schools })
+    }
+}
+
+/// The top trading cycles mechanism for school choice (Abdulkadiroğlu and Sönmez, 2003). Every
+/// school with a free seat points at its highest priority student who is still unassigned, and
+/// every unassigned student points at their favourite school that still has a free seat and
+/// is willing to admit them. Each cycle of students and schools trades: every student in it
+/// takes a seat at the school they point at. Schools leave once they're full, and students
+/// leave once they're assigned or run out of schools. With one seat per school and every
+/// school giving its "owner" top priority this is TTC, and with a common priority order it's
+/// serial dictatorship.
+///
+/// It's strategy-proof and Pareto efficient for students, but it doesn't eliminate justified
+/// envy: a student can lose a seat to someone with lower priority at that school.
+///
+/// https://doi.org/10.1257/000282803321455214
+pub struct PreferenceGraph<A, S> {
+    students: Vec<A>,
+    schools: Vec<S>,
+    // Every student's choices, without the schools that wouldn't admit them
+    prefs: Vec<Vec<usize>>,
+    priority: Vec<Vec<usize>>,
+    capacity: Vec<usize>,
+}
+
+impl<A, S> PreferenceGraph<A, S>
+where
+    A: Eq + Hash + Clone + Display,
+    S: Eq + Hash + Clone + Display,
+{
+    pub fn new(prefs: Preferences<A, S>) -> Result<Self, TTCError> {
+        let (students, choices): (Vec<A>, Vec<Vec<S>>) = prefs.prefs.into_iter().unzip();
+        if students.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        let (schools, details): (Vec<S>, Vec<School<A>>) = prefs.schools.into_iter().unzip();
+        let capacity = details.iter().map(|d| d.capacity).collect();
+        let student_index: HashMap<A, usize> = students
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (a.clone(), i))
+            .collect();
+        let school_index: HashMap<S, usize> = schools
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i))
+            .collect();
+        let priority: Vec<Vec<usize>> = details
+            .iter()
+            .zip(&schools)
+            .map(|(d, s)| {
+                d.priority
+                    .iter()
+                    .map(|a| {
+                        student_index
+                            .get(a)
+                            .copied()
+                            .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", s, a)))
+                    })
+                    .collect()
+            })
+            .collect::<Result<_, TTCError>>()?;
+        let eligible: HashSet<(usize, usize)> = priority
+            .iter()
+            .enumerate()
+            .flat_map(|(s, list)| list.iter().map(move |&a| (a, s)))
+            .collect();
+        let prefs = students
+            .iter()
+            .zip(&choices)
+            .enumerate()
+            .map(|(a, (name, list))| {
+                let mut ranked = vec![];
+                for s in list {
+                    let s = *school_index
+                        .get(s)
+                        .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", name, s)))?;
+                    if eligible.contains(&(a, s)) {
+                        ranked.push(s);
+                    }
+                }
+                Ok(ranked)
+            })
+            .collect::<Result<_, TTCError>>()?;
+
+        Ok(Self {
+            students,
+            schools,
+            prefs,
+            priority,
+            capacity,
+        })
+    }
+
+    /// Assign students to schools. Students that end up without a seat are left out of the
+    /// allocation.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(&self) -> Result<Allocation<A, S>, TTCError> {
+        let n = self.students.len();
+        let mut seats = self.capacity.clone();
+        let mut served = vec![false; n];
+        let mut on_stack = vec![false; n];
+        // Both sides only ever point further down their lists, since full schools and assigned
+        // students never come back
+        let mut cursor = vec![0usize; n];
+        let mut school_cursor = vec![0usize; self.schools.len()];
+        let mut allocation = HashMap::new();
+        // Students each pointing at a school which points at the student above them
+        let mut stack: Vec<usize> = Vec::new();
+
+        for next in 0..n {
+            if served[next] {
+                continue;
+            }
+            stack.push(next);
+            on_stack[next] = true;
+            while let Some(&a) = stack.last() {
+                let prefs = &self.prefs[a];
+                while cursor[a] < prefs.len() && seats[prefs[cursor[a]]] == 0 {
+                    cursor[a] += 1;
+                }
+                let Some(&s) = prefs.get(cursor[a]) else {
+                    // No school that would admit `a` has a seat left
+                    stack.pop();
+                    on_stack[a] = false;
+                    served[a] = true;
+                    continue;
+                };
+                // `a` itself is eligible and unassigned, so the school points at someone
+                let priority = &self.priority[s];
+                while served[priority[school_cursor[s]]] {
+                    school_cursor[s] += 1;
+                }
+                let b = priority[school_cursor[s]];
+                if !on_stack[b] {
+                    stack.push(b);
+                    on_stack[b] = true;
+                    continue;
+                }
+                // Everyone from `b` up points at a school that points at the student above
+                // them, and `a` points at a school that points at `b`
+                let from = stack
+                    .iter()
+                    .rposition(|&x| x == b)
+                    .ok_or(TTCError::AlwaysCycles)?;
+                for x in stack.split_off(from) {
+                    let s = self.prefs[x][cursor[x]];
+                    seats[s] -= 1;
+                    served[x] = true;
+                    on_stack[x] = false;
+                    allocation.insert(self.students[x].clone(), self.schools[s].clone());
+                }
+            }
+        }
+        Ok(Allocation { allocation })
+    }
+}
+
+#[cfg(any(test, feature = "test"))]
+pub mod test_utils {
+    use super::*;
+    use crate::test_utils;
+    use proptest::prelude::*;
+
+    impl<V> Arbitrary for Preferences<V, V>
+    where
+        V: Clone + Eq + Hash + core::fmt::Debug + Arbitrary + 'static,
+        V::Strategy: 'static,
+    {
+        // The range of the number of students and schools together. Up to half of them are
+        // schools, with up to 3 seats each.
+        type Parameters = Option<core::ops::RangeInclusive<usize>>;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(params: Self::Parameters) -> Self::Strategy {
+            let size_range = params.unwrap_or(2..=24);
+            prop::collection::hash_set(any::<V>(), size_range)
+                .prop_flat_map(|names| {
+                    let names: Vec<V> = names.into_iter().collect();
+                    let len = names.len();
+                    // Students and schools share a type, so split the names between them
+                    let schools = 1..=(len / 2).max(1);
+                    (Just(names), schools)
+                })
+                .prop_flat_map(|(mut names, m)| {
+                    let schools = names.split_off(names.len() - m);
+                    let n = names.len();
+                    let prefs = prop::collection::vec(prop::collection::vec(0..m, 0..=m), n);
+                    // A capacity, and a priority order cut to a random length
+                    let details = prop::collection::vec(
+                        (
+                            0..=3usize,
+                            Just((0..n).collect::<Vec<_>>()).prop_shuffle(),
+                            0..=n,
+                        ),
+                        m,
+                    );
+                    (Just(names), Just(schools), details, prefs)
+                })
+                .prop_map(|(students, names, details, prefs)| {
+                    let prefs = students
+                        .iter()
+                        .zip(prefs)
+                        .map(|(a, choices)| (a.clone(), test_utils::ranking(choices, &names)))
+                        .collect();
+                    let schools = names
+                        .iter()
+                        .zip(details)
+                        .map(|(s, (capacity, order, len))| {
+                            let priority = order[..len].iter().map(|&a| students[a].clone());
+                            (
+                                s.clone(),
+                                School {
+                                    capacity,
+                                    priority: priority.collect(),
+                                },
+                            )
+                        })
+                        .collect();
+                    Preferences { prefs, schools }
+                })
+                .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict;
+    use proptest::prelude::*;
+
+    fn school<A>(capacity: usize, priority: Vec<A>) -> School<A> {
+        School { capacity, priority }
+    }
+
+    #[test]
+    fn basic_test() {
+        let prefs = vec![
+            ("a", vec!["y", "x"]),
+            ("b", vec!["y", "x"]),
+            ("c", vec!["x"]),
+        ];
+        let schools = vec![
+            ("x", school(2, vec!["a", "b", "c"])),
+            ("y", school(1, vec!["c", "a", "b"])),
+        ];
+        let prefs =
+            Preferences::new(prefs.into_iter().collect(), schools.into_iter().collect()).unwrap();
+        let alloc = PreferenceGraph::new(prefs)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        // "a" trades its priority at "x" for "c"'s priority at "y", and then "x" still has a seat
+        // for "b"
+        assert_eq!(
+            alloc.allocation,
+            vec![("a", "y"), ("b", "x"), ("c", "x")]
+                .into_iter()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn eligibility_test() {
+        // "b" has top priority at "x", but "x" won't admit "a", so "b" can't trade with it
+        let prefs = vec![("a", vec!["x", "y"]), ("b", vec!["y"])];
+        let schools = vec![
+            ("x", school(1, vec!["b"])),
+            ("y", school(1, vec!["a", "b"])),
+        ];
+        let prefs =
+            Preferences::new(prefs.into_iter().collect(), schools.into_iter().collect()).unwrap();
+        let alloc = PreferenceGraph::new(prefs)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        assert_eq!(alloc.allocation, vec![("a", "y")].into_iter().collect());
+    }
+
+    #[test]
+    fn validation_test() {
+        let prefs: HashMap<&str, Vec<&str>> =
+            vec![("a", vec!["x"]), ("b", vec![])].into_iter().collect();
+        let schools = |priority| vec![("x", school(1, priority))].into_iter().collect();
+        assert!(matches!(
+            Preferences::new(prefs.clone(), HashMap::new()),
+            Err(SchoolError::InvalidChoice("a"))
+        ));
+        assert!(matches!(
+            Preferences::new(prefs.clone(), schools(vec!["a", "c"])),
+            Err(SchoolError::InvalidPriority("x"))
+        ));
+        assert!(matches!(
+            Preferences::new(prefs.clone(), schools(vec!["b", "a", "b"])),
+            Err(SchoolError::InvalidPriority("x"))
+        ));
+        assert!(Preferences::new(prefs, schools(vec!["b"])).is_ok());
+    }
+
+    // Rank of the school a student is assigned to in their list, with no school ranking last
+    fn rank(list: &[u32], school: Option<&u32>) -> usize {
+        school
+            .and_then(|s| list.iter().position(|x| x == s))
+            .unwrap_or(list.len())
+    }
+
+    fn solve(p: &Preferences<u32, u32>) -> Allocation<u32> {
+        PreferenceGraph::new(p.clone())
+            .unwrap()
+            .solve_preferences()
+            .unwrap()
+    }
+
+    proptest! {
+    #[test]
+    fn test_can_solve_random_problem(p in Preferences::<u32, u32>::arbitrary())
+      { let alloc = solve(&p);
+
+        // Check that schools admit no more students than they have seats, and only eligible
+        // students who applied
+        for (name, school) in &p.schools {
+            let admitted: Vec<&u32> = alloc.allocation.iter().filter(|(_, s)| *s == name).map(|(a, _)| a).collect();
+            prop_assert!(admitted.len() <= school.capacity);
+            for a in admitted {
+                prop_assert!(school.priority.contains(a));
+                prop_assert!(p.prefs[a].contains(name));
+            }
+        }
+
+        // Check that no seat is wasted: a student only misses out on a school they prefer and
+        // are eligible for if it's full
+        for (a, list) in &p.prefs {
+            let assigned = rank(list, alloc.allocation.get(a));
+            for s in &list[..assigned] {
+                let school = &p.schools[s];
+                if school.priority.contains(a) {
+                    let admitted = alloc.allocation.values().filter(|x| *x == s).count();
+                    prop_assert_eq!(admitted, school.capacity, "{} wastes a seat {} wants", s, a);
+                }
+            }
+        }
+      }
+
+    #[test]
+    fn test_is_strategy_proof(
+        (p, student) in Preferences::<u32, u32>::arbitrary_with(Some(2..=8)).prop_flat_map(|p| {
+            let students: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), prop::sample::select(students))
+        }))
+      { let list = p.prefs[&student].clone();
+        let truthful = rank(&list, solve(&p).allocation.get(&student));
+        // Every report of up to 2 schools
+        let schools: Vec<u32> = p.schools.keys().cloned().collect();
+        let mut reports = vec![vec![]];
+        for x in &schools {
+            reports.push(vec![*x]);
+            for y in schools.iter().filter(|y| *y != x) {
+                reports.push(vec![*x, *y]);
+            }
+        }
+        for report in reports {
+            let mut lie = p.clone();
+            lie.prefs.insert(student, report.clone());
+            let manipulated = rank(&list, solve(&lie).allocation.get(&student));
+            prop_assert!(manipulated >= truthful, "{} does better reporting {:?}", student, report);
+        }
+      }
+
+    #[test]
+    fn test_matches_ttc_with_unit_capacities(p in strict::Preferences::<u32>::arbitrary())
+      { // Every participant owns a school with one seat which gives them top priority, and
+        // ranks it last if they didn't rank it at all
+        let participants: Vec<u32> = p.prefs.keys().cloned().collect();
+        let schools = participants
+            .iter()
+            .map(|&v| {
+                let others = participants.iter().filter(|&&x| x != v);
+                (v, school(1, core::iter::once(v).chain(others.cloned()).collect()))
+            })
+            .collect();
+        let prefs = p
+            .prefs
+            .iter()
+            .map(|(&v, list)| {
+                let mut list = list.clone();
+                if !list.contains(&v) {
+                    list.push(v);
+                }
+                (v, list)
+            })
+            .collect();
+        let alloc = solve(&Preferences::new(prefs, schools).unwrap());
+        let ttc = strict::PreferenceGraph::new(p).unwrap().solve_preferences().unwrap();
+        prop_assert_eq!(alloc.allocation, Allocation::from(ttc).allocation);
+      }
+
+    #[test]
+    fn test_matches_serial_dictatorship_with_common_priority(
+        (p, priority) in Preferences::<u32, u32>::arbitrary().prop_flat_map(|p| {
+            let students: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), Just(students).prop_shuffle())
+        }))
+      { let mut p = p;
+        for school in p.schools.values_mut() {
+            school.priority = priority.clone();
+        }
+        // Each student in priority order takes their favourite school with a free seat
+        let mut seats: HashMap<u32, usize> = p.schools.iter().map(|(s, school)| (*s, school.capacity)).collect();
+        let mut allocation = HashMap::new();
+        for a in &priority {
+            if let Some(s) = p.prefs[a].iter().find(|s| seats[*s] > 0) {
+                *seats.get_mut(s).unwrap() -= 1;
+                allocation.insert(*a, *s);
+            }
+        }
+        prop_assert_eq!(solve(&p).allocation, allocation);
+      }
+    }
+}
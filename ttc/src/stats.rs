@@ -0,0 +1,136 @@
+use crate::strict::{Allocation, Preferences};
+use alloc::collections::BTreeMap;
+use core::{fmt, hash::Hash};
+
+/// Summary numbers for an allocation, measured against the preferences it was computed from.
+///
+/// Ranks are 0-based positions in a participant's preference list. An item the participant
+/// didn't list, including their own item if they left it out, ranks just after the whole list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationStats {
+    pub participants: usize,
+    /// How many participants received an item other than their own.
+    pub traded: usize,
+    /// How many participants received an item of each rank.
+    pub received_ranks: BTreeMap<usize, usize>,
+    /// How much better the received item ranks than the participant's own item, on average over
+    /// all participants. Negative if the mechanism isn't individually rational.
+    pub mean_rank_improvement: f64,
+    /// How many trading cycles there are of each length, counting participants who keep their
+    /// own item as cycles of length 1.
+    pub cycle_lengths: BTreeMap<usize, usize>,
+}
+
+impl AllocationStats {
+    pub fn new<V: Clone + Eq + Hash>(prefs: &Preferences<V>, alloc: &Allocation<V>) -> Self {
+        let rank = |v: &V, item: &V| {
+            prefs.get(v).map_or(0, |list| {
+                list.iter().position(|x| x == item).unwrap_or(list.len())
+            })
+        };
+        let mut traded = 0;
+        let mut received_ranks = BTreeMap::new();
+        let mut improvement = 0i64;
+        for (v, item) in alloc.allocation.iter() {
+            if v != item {
+                traded += 1;
+            }
+            let received = rank(v, item);
+            *received_ranks.entry(received).or_insert(0) += 1;
+            improvement += rank(v, v) as i64 - received as i64;
+        }
+        let mut cycle_lengths = BTreeMap::new();
+        for cycle in alloc.cycles() {
+            *cycle_lengths.entry(cycle.len()).or_insert(0) += 1;
+        }
+        let participants = alloc.allocation.len();
+        Self {
+            participants,
+            traded,
+            received_ranks,
+            mean_rank_improvement: if participants == 0 {
+                0.0
+            } else {
+                improvement as f64 / participants as f64
+            },
+            cycle_lengths,
+        }
+    }
+}
+
+impl fmt::Display for AllocationStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let histogram = |f: &mut fmt::Formatter<'_>, counts: &BTreeMap<usize, usize>| {
+            counts.iter().enumerate().try_for_each(|(i, (k, count))| {
+                write!(f, "{}{}: {}", if i > 0 { ", " } else { "" }, k, count)
+            })
+        };
+        writeln!(f, "traded: {} of {}", self.traded, self.participants)?;
+        writeln!(
+            f,
+            "mean rank improvement: {:.2}",
+            self.mean_rank_improvement
+        )?;
+        write!(f, "received ranks: ")?;
+        histogram(f, &self.received_ranks)?;
+        write!(f, "\ncycle lengths: ")?;
+        histogram(f, &self.cycle_lengths)?;
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::PreferenceGraph;
+    use proptest::prelude::*;
+
+    #[test]
+    fn stats_test() {
+        let prefs = vec![
+            (1, vec![2, 3]),
+            (2, vec![3, 1]),
+            (3, vec![1]),
+            (4, vec![1, 2]),
+        ];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let cycles = PreferenceGraph::new(prefs.clone())
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        let stats = AllocationStats::new(&prefs, &Allocation::from(cycles));
+        // 1, 2 and 3 trade in a cycle and get their first choices, 4 keeps its item
+        assert_eq!(stats.participants, 4);
+        assert_eq!(stats.traded, 3);
+        assert_eq!(
+            stats.received_ranks,
+            vec![(0, 3), (2, 1)].into_iter().collect()
+        );
+        assert_eq!(
+            stats.cycle_lengths,
+            vec![(1, 1), (3, 1)].into_iter().collect()
+        );
+        // 1 and 2 improve by 2, 3 by 1
+        assert_eq!(stats.mean_rank_improvement, 5.0 / 4.0);
+        assert_eq!(
+            stats.to_string(),
+            "traded: 3 of 4\nmean rank improvement: 1.25\nreceived ranks: 0: 3, 2: 1\ncycle lengths: 1: 1, 3: 1\n"
+        );
+    }
+
+    proptest! {
+    #[test]
+    fn test_stats_are_consistent(p in Preferences::<u32>::arbitrary())
+      { let cycles = PreferenceGraph::new(p.clone()).unwrap().solve_preferences().unwrap();
+        let stats = AllocationStats::new(&p, &Allocation::from(cycles));
+        prop_assert_eq!(stats.participants, p.prefs.len());
+        prop_assert_eq!(stats.received_ranks.values().sum::<usize>(), stats.participants);
+        let in_cycles: usize = stats.cycle_lengths.iter().map(|(len, count)| len * count).sum();
+        prop_assert_eq!(in_cycles, stats.participants);
+        prop_assert_eq!(stats.traded, stats.participants - stats.cycle_lengths.get(&1).unwrap_or(&0));
+        // TTC is individually rational
+        prop_assert!(stats.mean_rank_improvement >= 0.0);
+      }
+    }
+}
@@ -1,22 +1,40 @@
-use petgraph::{
-    graph::{DiGraph, NodeIndex},
-    visit::{depth_first_search, Control, DfsEvent},
-    Direction, Graph,
-};
-use std::{
-    collections::HashMap,
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::{
     fmt::{Debug, Display},
     hash::Hash,
 };
+use hashbrown::{HashMap, HashSet};
 use thiserror::Error;
+#[cfg(feature = "tracing")]
 use tracing::instrument;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent)
+)]
 pub struct Cycle<V> {
-    values: Vec<V>,
+    pub(crate) values: Vec<V>,
+}
+
+impl<V> Cycle<V> {
+    /// The participants in the cycle, where each one receives the item of the next and the last
+    /// receives the item of the first.
+    pub fn values(&self) -> &[V] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
 }
 
-impl<V: Eq + Clone + std::hash::Hash> PartialEq for Cycle<V> {
+impl<V: Eq + Clone + Hash> PartialEq for Cycle<V> {
     fn eq(&self, other: &Self) -> bool {
         if self.values.len() != other.values.len() {
             return false;
@@ -40,9 +58,113 @@ impl<V: Eq + Clone + std::hash::Hash> PartialEq for Cycle<V> {
     }
 }
 
+impl<V: Display> Display for Cycle<V> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "[")?;
+        for (i, v) in self.values.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", v)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<V: Ord> Cycle<V> {
+    /// Rotate the cycle so that it starts at its smallest element.
+    pub fn canonical(mut self) -> Self {
+        if let Some(ix) = self
+            .values
+            .iter()
+            .enumerate()
+            .min_by(|a, b| a.1.cmp(b.1))
+            .map(|(ix, _)| ix)
+        {
+            self.values.rotate_left(ix);
+        }
+        self
+    }
+}
+
+/// Put a solution in canonical form: every cycle starts at its smallest element, and the cycles
+/// are sorted. The same preferences always produce the same canonical solution, regardless of the
+/// order the solver happened to find the cycles in.
+pub fn canonical_cycles<V: Ord>(cycles: Vec<Cycle<V>>) -> Vec<Cycle<V>> {
+    let mut cycles: Vec<Cycle<V>> = cycles.into_iter().map(Cycle::canonical).collect();
+    cycles.sort_by(|a, b| a.values.cmp(&b.values));
+    cycles
+}
+
+/// Who receives which item. In a housing market every participant is identified with the item
+/// they deposit, so both sides have the same type, but mechanisms where some participants come
+/// without an item (see [`crate::housing`]) use a separate type `A` for participants.
 #[derive(Debug)]
-pub struct Allocation<V> {
-    pub allocation: HashMap<V, V>,
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        transparent,
+        bound(
+            serialize = "A: Eq + Hash + serde::Serialize, H: serde::Serialize",
+            deserialize = "A: Eq + Hash + serde::Deserialize<'de>, H: serde::Deserialize<'de>"
+        )
+    )
+)]
+pub struct Allocation<A, H = A> {
+    pub allocation: HashMap<A, H>,
+}
+
+impl<A, H> Allocation<A, H> {
+    /// The participant who receives `item`, if anyone does. This scans the whole allocation, so
+    /// use [`Allocation::inverse`] for repeated lookups.
+    pub fn receiver_of(&self, item: &H) -> Option<&A>
+    where
+        H: PartialEq,
+    {
+        self.allocation
+            .iter()
+            .find(|(_, h)| *h == item)
+            .map(|(a, _)| a)
+    }
+
+    /// Map every allocated item to the participant who receives it.
+    pub fn inverse(&self) -> HashMap<H, A>
+    where
+        A: Clone,
+        H: Clone + Eq + Hash,
+    {
+        self.allocation
+            .iter()
+            .map(|(a, h)| (h.clone(), a.clone()))
+            .collect()
+    }
+}
+
+impl<V: Clone + Eq + Hash> Allocation<V> {
+    /// Split the allocation back into its trading cycles, in no particular order. Participants
+    /// who keep their own item form cycles of length 1. Anyone whose item doesn't lead back to
+    /// them, because the allocation isn't a permutation, is left out.
+    pub fn cycles(&self) -> Vec<Cycle<V>> {
+        let mut seen = HashSet::new();
+        let mut res = Vec::new();
+        for start in self.allocation.keys() {
+            if seen.contains(start) {
+                continue;
+            }
+            let mut values = vec![start.clone()];
+            let mut next = self.allocation.get(start);
+            while let Some(v) = next.filter(|v| *v != start && !values.contains(*v)) {
+                values.push(v.clone());
+                next = self.allocation.get(v);
+            }
+            if next == Some(start) {
+                seen.extend(values.iter().cloned());
+                res.push(Cycle { values });
+            }
+        }
+        res
+    }
 }
 
 impl<V: Clone + Eq + Hash> From<Vec<Cycle<V>>> for Allocation<V> {
@@ -61,13 +183,76 @@ impl<V: Clone + Eq + Hash> From<Vec<Cycle<V>>> for Allocation<V> {
     }
 }
 
+/// An allocation backed by an ordered map, so that iterating over it is deterministic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(transparent, bound(deserialize = "V: Ord + serde::Deserialize<'de>"))
+)]
+pub struct CanonicalAllocation<V> {
+    pub allocation: BTreeMap<V, V>,
+}
+
+impl<V: Clone + Ord> From<Vec<Cycle<V>>> for CanonicalAllocation<V> {
+    fn from(cycles: Vec<Cycle<V>>) -> Self {
+        let mut allocation = BTreeMap::new();
+        cycles.into_iter().for_each(|cycle| {
+            cycle
+                .values
+                .iter()
+                .zip(cycle.values.iter().cycle().skip(1))
+                .for_each(|(a, b)| {
+                    allocation.insert(a.clone(), b.clone());
+                });
+        });
+        CanonicalAllocation { allocation }
+    }
+}
+
+impl<V: Ord> From<Allocation<V>> for CanonicalAllocation<V> {
+    fn from(alloc: Allocation<V>) -> Self {
+        CanonicalAllocation {
+            allocation: alloc.allocation.into_iter().collect(),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum PrefsError<V: Display> {
     #[error("{} has preferences for options that don't exist", _0)]
     InvalidChoice(V),
 }
 
+/// A problem with a preference profile, as reported by [`Preferences::validate`].
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ValidationError<V: Display> {
+    #[error("The pool is empty")]
+    EmptyPool,
+    #[error("{} ranks items that aren't in the pool", participant)]
+    InvalidChoice { participant: V, choices: Vec<V> },
+    #[error("{} ranks some items more than once", participant)]
+    DuplicateChoice { participant: V, choices: Vec<V> },
+    #[error(
+        "{} ranks its own item at position {} but ranks items after it",
+        participant,
+        position
+    )]
+    SelfRankedEarly { participant: V, position: usize },
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        transparent,
+        bound(
+            serialize = "V: Eq + Hash + serde::Serialize",
+            deserialize = "V: Eq + Hash + serde::Deserialize<'de>"
+        )
+    )
+)]
 pub struct Preferences<V> {
     pub prefs: HashMap<V, Vec<V>>,
 }
@@ -85,6 +270,21 @@ impl<V: Eq + Hash> Preferences<V> {
             .map(|prefs| prefs.iter().position(|v| v == &value).unwrap_or(usize::MAX))
     }
 
+    /// The rank of `item` for `participant`, where the participant's own item ranks wherever it
+    /// appears in their list (or after everything else if it doesn't). Returns `None` if the
+    /// participant would rather keep their own item.
+    pub fn acceptable_rank(&self, participant: &V, item: &V) -> Option<usize> {
+        let prefs = self.prefs.get(participant)?;
+        let own = prefs
+            .iter()
+            .position(|v| v == participant)
+            .unwrap_or(prefs.len());
+        if item == participant {
+            return Some(own);
+        }
+        prefs.iter().take(own).position(|v| v == item)
+    }
+
     pub fn get(&self, v: &V) -> Option<&Vec<V>> {
         self.prefs.get(v)
     }
@@ -117,6 +317,68 @@ where
         Ok(Self { prefs })
     }
 
+    /// Check the whole profile, collecting every problem rather than stopping at the first. A
+    /// participant's own item may appear in its list, but only as the last entry.
+    pub fn validate(&self) -> Result<(), Vec<ValidationError<V>>> {
+        if self.prefs.is_empty() {
+            return Err(vec![ValidationError::EmptyPool]);
+        }
+        let mut errors = vec![];
+        for (participant, vs) in self.prefs.iter() {
+            let invalid: Vec<V> = vs
+                .iter()
+                .filter(|v| !self.prefs.contains_key(*v))
+                .cloned()
+                .collect();
+            if !invalid.is_empty() {
+                errors.push(ValidationError::InvalidChoice {
+                    participant: participant.clone(),
+                    choices: invalid,
+                });
+            }
+            let mut seen = HashSet::new();
+            let mut duplicates: Vec<V> = vec![];
+            for v in vs {
+                if !seen.insert(v) && !duplicates.contains(v) {
+                    duplicates.push(v.clone());
+                }
+            }
+            if !duplicates.is_empty() {
+                errors.push(ValidationError::DuplicateChoice {
+                    participant: participant.clone(),
+                    choices: duplicates,
+                });
+            }
+            if let Some(position) = vs.iter().position(|v| v == participant) {
+                if position + 1 < vs.len() {
+                    errors.push(ValidationError::SelfRankedEarly {
+                        participant: participant.clone(),
+                        position,
+                    });
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Drop repeated entries, and cut every list at the participant's own item: nothing ranked
+    /// after it can ever be traded for, and ranking it last is the same as leaving it off. This
+    /// doesn't change the solution.
+    pub fn normalize(mut self) -> Self {
+        for (participant, vs) in self.prefs.iter_mut() {
+            let mut seen = HashSet::new();
+            vs.retain(|v| seen.insert(v.clone()));
+            if let Some(position) = vs.iter().position(|v| v == participant) {
+                vs.truncate(position);
+            }
+        }
+        self
+    }
+
     pub fn preferred_item(&self, v: V) -> V {
         self.prefs
             .get(&v)
@@ -128,6 +390,7 @@ where
         for v in chosen {
             self.prefs.remove_entry(v);
         }
+        let chosen: HashSet<&V> = chosen.iter().collect();
         for leftover_vs in self.prefs.values_mut() {
             leftover_vs.retain(|x| !chosen.contains(x))
         }
@@ -144,107 +407,193 @@ pub enum TTCError {
     AlwaysCycles,
 }
 
+/// Solves the housing market by pointer chasing: every participant keeps a cursor into its
+/// preference list which only ever moves forward, so the total work is linear in the number of
+/// participants plus the total length of the preference lists.
+///
+/// https://www.cis.upenn.edu/~aaroth/courses/slides/agt17/lect11.pdf
 pub struct PreferenceGraph<V> {
-    graph: DiGraph<V, ()>,
-    prefs: Preferences<V>,
+    pub(crate) nodes: Vec<V>,
+    // Preference lists as indices into `nodes`
+    prefs: Vec<Vec<usize>>,
+    // Position of each participant's current top choice in its preference list
+    cursor: Vec<usize>,
+    pub(crate) removed: Vec<bool>,
 }
 
 impl<V> PreferenceGraph<V>
 where
-    V: Eq + Display + Hash + Copy,
+    V: Clone + Eq + Hash,
 {
+    /// Intern the participants and translate the preference lists into indices. Fails with
+    /// [`TTCError::InvalidEdge`] if someone ranks an item that isn't in the pool, see
+    /// [`Preferences::validate`] to find out who.
     pub fn new(prefs: Preferences<V>) -> Result<Self, TTCError> {
-        let v: Vec<V> = prefs.prefs.keys().cloned().collect();
-        if v.is_empty() {
+        let (nodes, lists): (Vec<V>, Vec<Vec<V>>) = prefs.prefs.into_iter().unzip();
+        if nodes.is_empty() {
             return Err(TTCError::EmptyGraph);
         }
-        let e: Vec<(V, V)> = v.iter().map(|x| (*x, prefs.preferred_item(*x))).collect();
-
-        let graph = Self::setup(&v, &e);
-        Ok(Self { graph, prefs })
+        let prefs = {
+            let index: HashMap<&V, usize> = nodes.iter().enumerate().map(|(i, v)| (v, i)).collect();
+            lists
+                .iter()
+                .map(|list| {
+                    list.iter()
+                        .map(|x| {
+                            index.get(x).copied().ok_or_else(|| {
+                                TTCError::InvalidEdge(String::from(
+                                    "a participant ranks an item that isn't in the pool",
+                                ))
+                            })
+                        })
+                        .collect()
+                })
+                .collect::<Result<Vec<Vec<usize>>, TTCError>>()?
+        };
+
+        let n = nodes.len();
+        Ok(Self {
+            nodes,
+            prefs,
+            cursor: vec![0; n],
+            removed: vec![false; n],
+        })
     }
 
-    fn setup(v: &Vec<V>, e: &Vec<(V, V)>) -> DiGraph<V, ()> {
-        let mut g = Graph::new();
-        let mut m: HashMap<V, NodeIndex> = HashMap::new();
-
-        for &node in v {
-            let index = g.add_node(node);
-            m.insert(node, index);
+    // The participant's most preferred remaining item, or its own item if none are left
+    pub(crate) fn pointer(&mut self, v: usize) -> usize {
+        let prefs = &self.prefs[v];
+        while self.cursor[v] < prefs.len() && self.removed[prefs[self.cursor[v]]] {
+            self.cursor[v] += 1;
         }
+        prefs.get(self.cursor[v]).copied().unwrap_or(v)
+    }
 
-        for &(v1, v2) in e {
-            let n1 = m.get(&v1);
-            let n2 = m.get(&v2);
-
-            if let (Some(_n1), Some(_n2)) = (n1, n2) {
-                let _ = g.add_edge(*_n1, *_n2, ());
-            }
+    // One round of the textbook algorithm: point every participant in `remaining` at its most
+    // preferred remaining item, recording the targets in `pointer`, and return every cycle of
+    // the resulting graph. `walk` must hold `usize::MAX` for everyone in `remaining`, and is left
+    // that way. Nothing is removed.
+    pub(crate) fn top_cycles(
+        &mut self,
+        remaining: &[usize],
+        pointer: &mut [usize],
+        walk: &mut [usize],
+    ) -> Vec<Vec<usize>> {
+        for &v in remaining {
+            pointer[v] = self.pointer(v);
         }
-        g
-    }
-
-    /// https://www.cis.upenn.edu/~aaroth/courses/slides/agt17/lect11.pdf
-    #[instrument(skip_all, level = "info")]
-    fn find_cycle(&self) -> Result<Cycle<V>, TTCError> {
-        let mut predecessors = vec![NodeIndex::end(); self.graph.node_count()];
-        let start = self.graph.node_indices().next();
-        let cycle_end = depth_first_search(&self.graph, start, |event| match event {
-            DfsEvent::TreeEdge(u, v) => {
-                predecessors[v.index()] = u;
-                Control::Continue
+        // Every participant has exactly one pointer, so walking from anywhere either runs into
+        // an earlier walk or closes a new cycle. Marking each walk separately tells them apart.
+        let mut cycles = Vec::new();
+        for &start in remaining {
+            let mut v = start;
+            while walk[v] == usize::MAX {
+                walk[v] = start;
+                v = pointer[v];
             }
-            DfsEvent::BackEdge(u, v) => {
-                predecessors[v.index()] = u;
-                Control::Break(u)
+            if walk[v] == start {
+                let mut cycle = vec![v];
+                let mut u = pointer[v];
+                while u != v {
+                    cycle.push(u);
+                    u = pointer[u];
+                }
+                cycles.push(cycle);
             }
-            _ => Control::Continue,
-        })
-        .break_value()
-        .ok_or(TTCError::AlwaysCycles)?;
-
-        let mut cycle = vec![self.graph[cycle_end]];
-        let mut current_node = predecessors[cycle_end.index()];
-
-        while current_node != cycle_end {
-            let pred = predecessors[current_node.index()];
-            cycle.push(self.graph[current_node]);
-            current_node = pred;
         }
-        cycle.reverse();
-        Ok(Cycle { values: cycle })
+        for &v in remaining {
+            walk[v] = usize::MAX;
+        }
+        cycles
     }
 
-    #[instrument(skip_all, level = "info")]
+    /// Solve the market the way the textbook algorithm does, removing every cycle of the
+    /// pointer graph in each round. The result lists the cycles removed in each round, in no
+    /// particular order within a round, so `rounds[0]` are the cycles of round 1. This gives the
+    /// same cycles as [`PreferenceGraph::solve_preferences`] in the same linear time.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_rounds(&mut self) -> Result<Vec<Vec<Cycle<V>>>, TTCError> {
+        // A cycle is in the pointer graph from the round after every item its members prefer to
+        // their targets has left, and the pointer chasing only skips items that already left. So
+        // the round of every cycle follows from rounds that are already known.
+        let mut left_in = vec![0; self.nodes.len()];
+        let mut rounds: Vec<Vec<Cycle<V>>> = Vec::new();
+        self.chase(|g, cycle| {
+            let round = 1 + cycle
+                .iter()
+                .flat_map(|&v| &g.prefs[v][..g.cursor[v]])
+                .map(|&x| left_in[x])
+                .max()
+                .unwrap_or(0);
+            for &v in &cycle {
+                left_in[v] = round;
+            }
+            if rounds.len() < round {
+                rounds.resize_with(round, Vec::new);
+            }
+            rounds[round - 1].push(Cycle {
+                values: cycle.into_iter().map(|v| g.nodes[v].clone()).collect(),
+            });
+        })?;
+        Ok(rounds)
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
     pub fn solve_preferences(&mut self) -> Result<Vec<Cycle<V>>, TTCError> {
         let mut res = Vec::new();
-        while self.graph.node_count() > 0 {
-            let cycle = self.find_cycle()?;
-            self.prefs.remove_prefs(&cycle.values);
-            for v in &cycle.values {
-                let ix = self
-                    .graph
-                    .node_indices()
-                    .find(|&index| self.graph[index] == *v)
-                    .unwrap();
-                let mut edges_to_add = vec![];
-                for n in self.graph.neighbors_directed(ix, Direction::Incoming) {
-                    let preferred_item = self.prefs.preferred_item(self.graph[n]);
-                    let preferred_ix = self
-                        .graph
-                        .node_indices()
-                        .find(|&index| self.graph[index] == preferred_item)
-                        .unwrap();
-                    edges_to_add.push((n, preferred_ix));
+        self.chase(|g, cycle| {
+            res.push(Cycle {
+                values: cycle.into_iter().map(|v| g.nodes[v].clone()).collect(),
+            })
+        })?;
+        Ok(res)
+    }
+
+    // Remove every cycle, handing each to `on_cycle` as soon as it's removed
+    fn chase(&mut self, mut on_cycle: impl FnMut(&Self, Vec<usize>)) -> Result<(), TTCError> {
+        let n = self.nodes.len();
+        let mut path: Vec<usize> = Vec::new();
+        let mut on_path = vec![false; n];
+
+        for start in 0..n {
+            if self.removed[start] {
+                continue;
+            }
+            path.push(start);
+            on_path[start] = true;
+            // Follow pointers until we revisit the path. The revisited suffix is a cycle, and
+            // whoever pointed into it is left on top of the path to pick a new target.
+            while let Some(&current) = path.last() {
+                let next = self.pointer(current);
+                if !on_path[next] {
+                    path.push(next);
+                    on_path[next] = true;
+                    continue;
                 }
-                for e in edges_to_add {
-                    self.graph.add_edge(e.0, e.1, ());
+                let from = path
+                    .iter()
+                    .rposition(|&v| v == next)
+                    .ok_or(TTCError::AlwaysCycles)?;
+                let cycle: Vec<usize> = path.drain(from..).collect();
+                for &v in &cycle {
+                    on_path[v] = false;
+                    self.removed[v] = true;
                 }
-                self.graph.remove_node(ix);
+                on_cycle(self, cycle);
             }
-            res.push(cycle);
         }
-        Ok(res)
+        Ok(())
+    }
+}
+
+impl<V> PreferenceGraph<V>
+where
+    V: Ord + Clone + Hash,
+{
+    /// Like [`PreferenceGraph::solve_preferences`], but returns the solution in canonical form
+    /// (see [`canonical_cycles`]).
+    pub fn solve_canonical(&mut self) -> Result<Vec<Cycle<V>>, TTCError> {
+        self.solve_preferences().map(canonical_cycles)
     }
 }
 
@@ -335,6 +684,34 @@ mod tests {
         );
     }
 
+    #[test]
+    fn rounds_test() {
+        // 1 and 2 swap and so do 3 and 4, which leaves 5 and 6 to swap in the second round
+        let prefs = vec![
+            (1, vec![2]),
+            (2, vec![1]),
+            (3, vec![4]),
+            (4, vec![3]),
+            (5, vec![1, 3, 6]),
+            (6, vec![5]),
+        ];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let rounds: Vec<Vec<Cycle<u32>>> = PreferenceGraph::new(prefs)
+            .unwrap()
+            .solve_rounds()
+            .unwrap()
+            .into_iter()
+            .map(canonical_cycles)
+            .collect();
+        assert_eq!(
+            rounds,
+            vec![
+                vec![Cycle { values: vec![1, 2] }, Cycle { values: vec![3, 4] }],
+                vec![Cycle { values: vec![5, 6] }],
+            ]
+        );
+    }
+
     impl<V: PartialEq> Cycle<V> {
         fn intersection(&self, other: &Self) -> Vec<&V> {
             self.values
@@ -348,6 +725,7 @@ mod tests {
         }
     }
 
+    #[allow(clippy::expect_fun_call)]
     fn should_exchange<V: Debug + Eq + Hash + Copy>(
         prefs: &Preferences<V>,
         alloc: &Allocation<V>,
@@ -365,8 +743,207 @@ mod tests {
         a_better && b_better
     }
 
+    #[test]
+    fn non_copy_ids_test() {
+        // Structured keys need neither Copy nor Display
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        struct Token {
+            collection: String,
+            id: u32,
+        }
+        let token = |collection: &str, id| Token {
+            collection: collection.to_string(),
+            id,
+        };
+        let prefs = Preferences {
+            prefs: vec![
+                (token("a", 1), vec![token("b", 1)]),
+                (token("b", 1), vec![token("a", 1)]),
+                (token("a", 2), vec![token("a", 1)]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let cycles = PreferenceGraph::new(prefs.clone())
+            .unwrap()
+            .solve_canonical()
+            .unwrap();
+        assert_eq!(
+            cycles,
+            vec![
+                Cycle {
+                    values: vec![token("a", 1), token("b", 1)]
+                },
+                Cycle {
+                    values: vec![token("a", 2)]
+                },
+            ]
+        );
+
+        let mut invalid = prefs;
+        invalid.prefs.insert(token("c", 1), vec![token("d", 1)]);
+        assert!(matches!(
+            PreferenceGraph::new(invalid),
+            Err(TTCError::InvalidEdge(_))
+        ));
+    }
+
+    #[test]
+    fn canonical_test() {
+        let cycles = vec![
+            Cycle { values: vec![6, 4] },
+            Cycle { values: vec![3] },
+            Cycle {
+                values: vec![2, 5, 1],
+            },
+        ];
+        let canonical: Vec<Vec<u32>> = canonical_cycles(cycles)
+            .into_iter()
+            .map(|c| c.values)
+            .collect();
+        assert_eq!(canonical, vec![vec![1, 2, 5], vec![3], vec![4, 6]]);
+    }
+
+    #[test]
+    fn validation_test() {
+        let prefs = Preferences {
+            prefs: vec![
+                (1, vec![2, 4, 2]),
+                (2, vec![2, 1]),
+                (3, vec![1, 3]),
+                (4, vec![]),
+            ]
+            .into_iter()
+            .collect(),
+        };
+        let mut errors = prefs.validate().unwrap_err();
+        errors.sort_by_key(|e| e.to_string());
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::DuplicateChoice {
+                    participant: 1,
+                    choices: vec![2]
+                },
+                ValidationError::SelfRankedEarly {
+                    participant: 2,
+                    position: 0
+                },
+            ]
+        );
+
+        let prefs = Preferences {
+            prefs: vec![(1, vec![5, 2, 6, 5]), (2, vec![])]
+                .into_iter()
+                .collect(),
+        };
+        let mut errors = prefs.validate().unwrap_err();
+        errors.sort_by_key(|e| e.to_string());
+        assert_eq!(
+            errors,
+            vec![
+                ValidationError::InvalidChoice {
+                    participant: 1,
+                    choices: vec![5, 6, 5]
+                },
+                ValidationError::DuplicateChoice {
+                    participant: 1,
+                    choices: vec![5]
+                },
+            ]
+        );
+
+        let empty: Preferences<u32> = Preferences {
+            prefs: HashMap::new(),
+        };
+        assert_eq!(empty.validate(), Err(vec![ValidationError::EmptyPool]));
+    }
+
+    #[test]
+    fn normalize_test() {
+        let prefs = Preferences {
+            prefs: vec![(1, vec![2, 3, 2, 1, 3]), (2, vec![2, 1]), (3, vec![1, 3])]
+                .into_iter()
+                .collect(),
+        }
+        .normalize();
+        assert_eq!(prefs.get(&1), Some(&vec![2, 3]));
+        assert_eq!(prefs.get(&2), Some(&vec![]));
+        assert_eq!(prefs.get(&3), Some(&vec![1]));
+        assert_eq!(prefs.validate(), Ok(()));
+    }
+
+    #[test]
+    fn allocation_queries_test() {
+        let cycles = vec![
+            Cycle {
+                values: vec![1, 2, 3],
+            },
+            Cycle { values: vec![4] },
+        ];
+        let alloc = Allocation::from(cycles.clone());
+        assert_eq!(alloc.receiver_of(&2), Some(&1));
+        assert_eq!(alloc.receiver_of(&5), None);
+        assert_eq!(alloc.inverse()[&1], 3);
+        assert_eq!(canonical_cycles(alloc.cycles()), cycles);
+
+        // 5 receives 1's item, but nobody receives 5's
+        let alloc = Allocation {
+            allocation: vec![(1, 2), (2, 1), (5, 1)].into_iter().collect(),
+        };
+        assert_eq!(alloc.cycles(), vec![Cycle { values: vec![1, 2] }]);
+    }
+
     proptest! {
     #[test]
+    fn test_allocation_queries_agree(p in Preferences::<u32>::arbitrary())
+      { let cycles = PreferenceGraph::new(p).unwrap().solve_canonical().unwrap();
+        let alloc = Allocation::from(cycles.clone());
+        prop_assert_eq!(canonical_cycles(alloc.cycles()), cycles);
+        let inverse = alloc.inverse();
+        for (a, h) in alloc.allocation.iter() {
+            prop_assert_eq!(inverse[h], *a);
+            prop_assert_eq!(alloc.receiver_of(h), Some(a));
+        }
+      }
+
+    #[test]
+    fn test_canonical_solution_is_deterministic(p in Preferences::<u32>::arbitrary())
+      { // collecting into a new map gives a different iteration order
+        let reordered = Preferences {
+            prefs: p.prefs.iter().map(|(k, vs)| (*k, vs.clone())).collect(),
+        };
+        let solve = |p: Preferences<u32>| {
+            let cycles = PreferenceGraph::new(p).unwrap().solve_canonical().unwrap();
+            let values: Vec<Vec<u32>> = cycles.iter().map(|c| c.values.clone()).collect();
+            (values, CanonicalAllocation::from(cycles))
+        };
+        prop_assert_eq!(solve(p), solve(reordered));
+      }
+
+    #[test]
+    fn test_normalize_preserves_solution(p in Preferences::<u32>::arbitrary())
+      { // repeating every list adds duplicates and puts the self-entry in the middle
+        let messy = Preferences {
+            prefs: p.prefs.iter().map(|(k, vs)| (*k, vs.iter().chain(vs).cloned().collect())).collect(),
+        };
+        let normalized = messy.normalize();
+        prop_assert_eq!(normalized.validate(), Ok(()));
+        let solve = |p: Preferences<u32>| PreferenceGraph::new(p).unwrap().solve_canonical().unwrap();
+        prop_assert_eq!(solve(p), solve(normalized));
+      }
+
+    #[test]
+    fn test_rounds_match_solution(p in Preferences::<u32>::arbitrary())
+      { let rounds = PreferenceGraph::new(p.clone()).unwrap().solve_rounds().unwrap();
+        let solution = PreferenceGraph::new(p.clone()).unwrap().solve_canonical().unwrap();
+        prop_assert!(rounds.iter().all(|cycles| !cycles.is_empty()));
+        prop_assert_eq!(canonical_cycles(rounds.into_iter().flatten().collect()), solution);
+      }
+
+    // Written before these lints existed
+    #[allow(clippy::clone_on_copy, clippy::expect_fun_call, clippy::needless_borrow)]
+    #[test]
     fn test_can_solve_random_graph(p in Preferences::<u32>::arbitrary())
       { let mut g = PreferenceGraph::new(p.clone()).unwrap();
         let solution = g.solve_preferences();
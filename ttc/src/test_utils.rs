@@ -0,0 +1,37 @@
+//! Scaffolding shared by the [`Arbitrary`] instances of the preference types.
+use crate::strict::Preferences;
+use proptest::prelude::*;
+use std::{fmt::Debug, hash::Hash, ops::RangeInclusive};
+
+/// Draw a uniform strict profile of `size` participants together with whatever `extra` builds
+/// from it. The [`Arbitrary`] instances of the other preference types start from a strict
+/// profile and use this to draw the parts they add on top, such as ties, owners or a waitlist.
+pub(crate) fn strict_with<V, S>(
+    size: Option<RangeInclusive<usize>>,
+    extra: impl Fn(&Preferences<V>) -> S + 'static,
+) -> impl Strategy<Value = (Preferences<V>, S::Value)>
+where
+    V: Clone + Eq + Hash + Debug + Arbitrary + 'static,
+    V::Strategy: 'static,
+    S: Strategy,
+{
+    Preferences::<V>::arbitrary_with(size).prop_flat_map(move |prefs| {
+        let extra = extra(&prefs);
+        (Just(prefs), extra)
+    })
+}
+
+/// Turn a list of indices into `options` into a ranking, keeping the first occurrence of each
+/// option.
+pub(crate) fn ranking<V: Clone + PartialEq>(
+    choices: impl IntoIterator<Item = usize>,
+    options: &[V],
+) -> Vec<V> {
+    let mut ranking: Vec<V> = vec![];
+    for ix in choices {
+        if !ranking.contains(&options[ix]) {
+            ranking.push(options[ix].clone());
+        }
+    }
+    ranking
+}
@@ -0,0 +1,261 @@
+use crate::strict::{canonical_cycles, Cycle, PreferenceGraph, Preferences, TTCError};
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use core::{
+    fmt::{self, Display},
+    hash::Hash,
+};
+
+/// One round of the traced solver: the pointer graph at the start of the round, and the cycles
+/// that leave it. Rounds are numbered from 1.
+#[derive(Debug, Clone)]
+pub struct Round<V> {
+    pub round: usize,
+    /// Every remaining participant's most preferred remaining item (possibly its own)
+    pub pointers: BTreeMap<V, V>,
+    /// Every cycle of the pointer graph, which are all removed this round, in canonical form
+    pub cycles: Vec<Cycle<V>>,
+    /// Participants that stay in the market but were pointing into a cycle, so they have to
+    /// point somewhere else next round
+    pub redirected: Vec<V>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Trace<V> {
+    pub rounds: Vec<Round<V>>,
+}
+
+/// An item that a participant ranked above the one it received, and how it left the market.
+#[derive(Debug, Clone)]
+pub struct Departure<V> {
+    pub item: V,
+    pub round: usize,
+    pub cycle: Cycle<V>,
+}
+
+/// Why a participant received what it did: everything it ranked higher left the market first.
+#[derive(Debug, Clone)]
+pub struct Explanation<V> {
+    pub participant: V,
+    pub received: V,
+    pub round: usize,
+    pub cycle: Cycle<V>,
+    /// The items ranked above `received`, in order of preference
+    pub missed: Vec<Departure<V>>,
+}
+
+impl<V> PreferenceGraph<V>
+where
+    V: Ord + Clone + Hash,
+{
+    /// Like [`PreferenceGraph::solve_rounds`], but also records the whole pointer graph in
+    /// every round. This costs time proportional to the number of remaining participants in
+    /// every round, so it's meant for explaining a solution rather than computing one.
+    pub fn solve_traced(&mut self) -> Result<Trace<V>, TTCError> {
+        let n = self.nodes.len();
+        // Visit participants in order so that the trace is deterministic
+        let mut remaining: Vec<usize> = (0..n).filter(|&v| !self.removed[v]).collect();
+        remaining.sort_by(|&a, &b| self.nodes[a].cmp(&self.nodes[b]));
+        let mut pointer = vec![0; n];
+        let mut walk = vec![usize::MAX; n];
+        let mut rounds = vec![];
+
+        while !remaining.is_empty() {
+            let cycles = self.top_cycles(&remaining, &mut pointer, &mut walk);
+            if cycles.is_empty() {
+                return Err(TTCError::AlwaysCycles);
+            }
+            let pointers = remaining
+                .iter()
+                .map(|&v| (self.nodes[v].clone(), self.nodes[pointer[v]].clone()))
+                .collect();
+            for &v in cycles.iter().flatten() {
+                self.removed[v] = true;
+            }
+            remaining.retain(|&v| !self.removed[v]);
+
+            let redirected = remaining
+                .iter()
+                .filter(|&&v| self.removed[pointer[v]])
+                .map(|&v| self.nodes[v].clone())
+                .collect();
+            let cycles = cycles
+                .into_iter()
+                .map(|cycle| Cycle {
+                    values: cycle.into_iter().map(|v| self.nodes[v].clone()).collect(),
+                })
+                .collect();
+            rounds.push(Round {
+                round: rounds.len() + 1,
+                pointers,
+                cycles: canonical_cycles(cycles),
+                redirected,
+            });
+        }
+        Ok(Trace { rounds })
+    }
+}
+
+impl<V> Trace<V>
+where
+    V: Ord + Clone,
+{
+    /// The solution, in the same form as [`PreferenceGraph::solve_preferences`].
+    pub fn cycles(&self) -> Vec<Cycle<V>> {
+        self.rounds
+            .iter()
+            .flat_map(|r| r.cycles.iter().cloned())
+            .collect()
+    }
+
+    // The round and cycle in which `participant` leaves, and the item it takes with it
+    fn departure(&self, participant: &V) -> Option<(usize, &Cycle<V>, V)> {
+        self.rounds.iter().find_map(|r| {
+            r.cycles.iter().find_map(|cycle| {
+                let values = &cycle.values;
+                let ix = values.iter().position(|v| v == participant)?;
+                Some((r.round, cycle, values[(ix + 1) % values.len()].clone()))
+            })
+        })
+    }
+
+    /// Explain what `participant` received: the round and cycle it traded in, and when each of
+    /// the items it preferred left the market. `prefs` must be the preferences that were solved.
+    pub fn explain(&self, prefs: &Preferences<V>, participant: &V) -> Option<Explanation<V>>
+    where
+        V: Hash,
+    {
+        let (round, cycle, received) = self.departure(participant)?;
+        let missed = prefs
+            .get(participant)?
+            .iter()
+            .take_while(|&v| v != &received && v != participant)
+            .filter_map(|item| {
+                let (round, cycle, _) = self.departure(item)?;
+                Some(Departure {
+                    item: item.clone(),
+                    round,
+                    cycle: cycle.clone(),
+                })
+            })
+            .collect();
+        Some(Explanation {
+            participant: participant.clone(),
+            received,
+            round,
+            cycle: cycle.clone(),
+            missed,
+        })
+    }
+}
+
+impl<V: Display + PartialEq> Display for Explanation<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.received == self.participant {
+            write!(f, "{} kept its own item", self.participant)?;
+        } else {
+            write!(
+                f,
+                "{} received {} in round {} via cycle {}",
+                self.participant, self.received, self.round, self.cycle
+            )?;
+        }
+        for d in &self.missed {
+            write!(
+                f,
+                "; {} left in round {} via cycle {}",
+                d.item, d.round, d.cycle
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn basic_test() {
+        let prefs = vec![
+            ("S1", vec!["S3", "S2", "S4", "S1"]),
+            ("S2", vec!["S3", "S5", "S6"]),
+            ("S3", vec!["S3", "S1"]),
+            ("S4", vec!["S2", "S5", "S6", "S4"]),
+            ("S5", vec!["S1", "S3", "S2"]),
+            ("S6", vec!["S2", "S4", "S5", "S6"]),
+        ];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let trace = PreferenceGraph::new(prefs.clone())
+            .unwrap()
+            .solve_traced()
+            .unwrap();
+
+        let first = &trace.rounds[0];
+        assert_eq!(first.pointers["S1"], "S3");
+        assert_eq!(first.pointers["S3"], "S3");
+        assert_eq!(first.cycles, vec![Cycle { values: vec!["S3"] }]);
+        assert_eq!(first.redirected, vec!["S1", "S2"]);
+        assert_eq!(trace.rounds.len(), 3);
+
+        let explanation = trace.explain(&prefs, &"S2").unwrap();
+        assert_eq!(explanation.received, "S5");
+        assert_eq!(explanation.round, 2);
+        assert_eq!(explanation.missed.len(), 1);
+        assert_eq!(explanation.missed[0].item, "S3");
+        assert_eq!(explanation.missed[0].round, 1);
+        assert_eq!(
+            explanation.to_string(),
+            "S2 received S5 in round 2 via cycle [S1, S2, S5]; S3 left in round 1 via cycle [S3]"
+        );
+        assert!(trace.explain(&prefs, &"S7").is_none());
+    }
+
+    proptest! {
+    #[test]
+    fn test_trace_matches_solution(p in Preferences::<u32>::arbitrary())
+      { let trace = PreferenceGraph::new(p.clone()).unwrap().solve_traced().unwrap();
+        let solution = PreferenceGraph::new(p.clone()).unwrap().solve_canonical().unwrap();
+        prop_assert_eq!(canonical_cycles(trace.cycles()), solution);
+
+        for round in &trace.rounds {
+            // Everyone in a cycle is pointing at the next participant
+            for cycle in &round.cycles {
+                let values = &cycle.values;
+                for (a, b) in values.iter().zip(values.iter().cycle().skip(1)) {
+                    prop_assert_eq!(round.pointers[a], *b);
+                }
+            }
+            // and every cycle was found, so following pointers from anyone leads into one
+            let in_cycles: HashSet<u32> = round.cycles.iter().flat_map(|c| c.values.clone()).collect();
+            for start in round.pointers.keys() {
+                let mut v = *start;
+                for _ in 0..round.pointers.len() {
+                    v = round.pointers[&v];
+                }
+                prop_assert!(in_cycles.contains(&v));
+            }
+        }
+        // The linear solver puts every cycle in the same round as the textbook algorithm
+        let rounds: Vec<Vec<Cycle<u32>>> = PreferenceGraph::new(p.clone())
+            .unwrap()
+            .solve_rounds()
+            .unwrap()
+            .into_iter()
+            .map(canonical_cycles)
+            .collect();
+        let traced: Vec<Vec<Cycle<u32>>> = trace.rounds.iter().map(|r| r.cycles.clone()).collect();
+        prop_assert_eq!(rounds, traced);
+
+        for participant in p.prefs.keys() {
+            let explanation = trace.explain(&p, participant).unwrap();
+            // Everything the participant preferred left strictly earlier
+            for d in &explanation.missed {
+                prop_assert!(d.round < explanation.round);
+            }
+        }
+      }
+    }
+}
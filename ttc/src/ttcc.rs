@@ -0,0 +1,550 @@
+use crate::strict::{self, Allocation, Cycle, PrefsError, TTCError};
+use alloc::{collections::VecDeque, format, vec, vec::Vec};
+use core::{fmt::Display, hash::Hash};
+use hashbrown::HashMap;
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+/// An entry in a TTCC preference list: either a deposited item, or giving your item to the
+/// waitlist in exchange for nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Choice<V> {
+    Item(V),
+    Waitlist,
+}
+
+/// Preferences for a pool with a waitlist. As in [`strict::Preferences`], every depositor is
+/// identified with the item they deposit. Ranking [`Choice::Waitlist`] means being willing to give
+/// your item away rather than receive anything ranked below it, so a list of just `[Waitlist]`
+/// donates the item. The waitlist is an ordered queue of applicants who have nothing to deposit,
+/// and each item that reaches the waitlist goes to the next applicant in line.
+#[derive(Debug, Clone)]
+pub struct Preferences<V> {
+    pub prefs: HashMap<V, Vec<Choice<V>>>,
+    pub waitlist: Vec<V>,
+}
+
+impl<V: Eq + Hash> Preferences<V> {
+    pub fn get(&self, v: &V) -> Option<&Vec<Choice<V>>> {
+        self.prefs.get(v)
+    }
+
+    /// The position of `choice` in `participant`'s list, where the participant's own item ranks
+    /// after everything if it isn't listed. Returns `None` for choices ranked below its own item.
+    pub fn acceptable_rank(&self, participant: &V, choice: &Choice<V>) -> Option<usize> {
+        let prefs = self.prefs.get(participant)?;
+        let own = Choice::Item(participant);
+        let own = prefs
+            .iter()
+            .position(|c| c.as_ref() == own)
+            .unwrap_or(prefs.len());
+        if choice.as_ref() == Choice::Item(participant) {
+            return Some(own);
+        }
+        prefs
+            .iter()
+            .take(own)
+            .position(|c| c.as_ref() == choice.as_ref())
+    }
+}
+
+impl<V> Choice<V> {
+    pub fn as_ref(&self) -> Choice<&V> {
+        match self {
+            Choice::Item(v) => Choice::Item(v),
+            Choice::Waitlist => Choice::Waitlist,
+        }
+    }
+}
+
+impl<V> Preferences<V>
+where
+    V: Display + Eq + Hash + Clone,
+{
+    pub fn new(prefs: HashMap<V, Vec<Choice<V>>>, waitlist: Vec<V>) -> Result<Self, PrefsError<V>> {
+        for (k, vs) in prefs.iter() {
+            let valid = vs.iter().all(|c| match c {
+                Choice::Item(v) => prefs.contains_key(v),
+                Choice::Waitlist => true,
+            });
+            if !valid {
+                return Err(PrefsError::InvalidChoice(k.clone()));
+            }
+        }
+        if let Some(applicant) = waitlist.iter().find(|a| prefs.contains_key(*a)) {
+            return Err(PrefsError::InvalidChoice(applicant.clone()));
+        }
+        Ok(Self { prefs, waitlist })
+    }
+}
+
+impl<V: Eq + Hash> From<strict::Preferences<V>> for Preferences<V> {
+    fn from(prefs: strict::Preferences<V>) -> Self {
+        Preferences {
+            prefs: prefs
+                .prefs
+                .into_iter()
+                .map(|(k, vs)| (k, vs.into_iter().map(Choice::Item).collect()))
+                .collect(),
+            waitlist: vec![],
+        }
+    }
+}
+
+/// How to pick a chain when the pointer graph has no cycles left, at which point every remaining
+/// item is on a chain that ends at the waitlist. Ties are broken by item order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainSelection<V> {
+    /// Take a chain of one item, i.e. send a single item to the waitlist
+    Shortest,
+    /// Take the longest chain, so that as many participants as possible trade
+    Longest,
+    /// Take the chain starting at the first participant in this order that's still in the
+    /// market. Participants that aren't listed come last.
+    Priority(Vec<V>),
+}
+
+/// A chain of trades ending at the waitlist. The owner of each item receives the next item, and
+/// the owner of the last item receives nothing. The first item isn't traded: it stays in the
+/// market without an owner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chain<V> {
+    pub values: Vec<V>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Outcome<V> {
+    pub cycles: Vec<Cycle<V>>,
+    /// Chains of two or more items, in the order they were selected
+    pub chains: Vec<Chain<V>>,
+    /// Items given to the waitlist, in order, with the applicant that received each one, or
+    /// `None` if every applicant had already been served
+    pub waitlisted: Vec<(V, Option<V>)>,
+}
+
+impl<V: Clone + Eq + Hash> Outcome<V> {
+    /// Who receives which item. Depositors whose item went to the waitlist don't appear as keys,
+    /// and applicants appear as keys if they received an item.
+    pub fn allocation(&self) -> Allocation<V> {
+        let mut alloc = Allocation::from(self.cycles.clone());
+        for chain in &self.chains {
+            for (a, b) in chain.values.iter().zip(chain.values.iter().skip(1)) {
+                alloc.allocation.insert(a.clone(), b.clone());
+            }
+        }
+        for (item, applicant) in &self.waitlisted {
+            if let Some(applicant) = applicant {
+                alloc.allocation.insert(applicant.clone(), item.clone());
+            }
+        }
+        alloc
+    }
+
+    /// Items that reached the waitlist after every applicant had been served.
+    pub fn unclaimed(&self) -> Vec<&V> {
+        self.waitlisted
+            .iter()
+            .filter(|(_, applicant)| applicant.is_none())
+            .map(|(item, _)| item)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Item(usize),
+    Waitlist,
+}
+
+/// Top trading cycles and chains (Roth, Sönmez and Ünver, 2004). Every item points at its
+/// owner's top remaining choice, and cycles trade as in [`strict::PreferenceGraph`]. When no
+/// cycles are left, a chain ending at the waitlist is selected and traded: each owner on the chain
+/// receives the next item, and the owner of the last item gives it up for nothing. The owner at
+/// the head of the chain leaves with its new item, but its own item stays in the market without
+/// an owner, pointing at the waitlist until someone else takes it. A chain of just one item sends
+/// that item to the next applicant.
+///
+/// https://web.stanford.edu/~alroth/papers/kidney.qje.pdf
+pub struct PreferenceGraph<V> {
+    nodes: Vec<V>,
+    prefs: Vec<Vec<Target>>,
+    applicants: VecDeque<V>,
+    selection: ChainSelection<V>,
+}
+
+impl<V> PreferenceGraph<V>
+where
+    V: Ord + Display + Hash + Clone,
+{
+    pub fn new(prefs: Preferences<V>, selection: ChainSelection<V>) -> Result<Self, TTCError> {
+        let mut nodes: Vec<V> = prefs.prefs.keys().cloned().collect();
+        if nodes.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        nodes.sort();
+        let index: HashMap<V, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), i))
+            .collect();
+        let targets = nodes
+            .iter()
+            .map(|v| {
+                prefs.prefs[v]
+                    .iter()
+                    .map(|c| match c {
+                        Choice::Item(x) => index
+                            .get(x)
+                            .map(|&ix| Target::Item(ix))
+                            .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", v, x))),
+                        Choice::Waitlist => Ok(Target::Waitlist),
+                    })
+                    .collect()
+            })
+            .collect::<Result<Vec<Vec<Target>>, TTCError>>()?;
+
+        Ok(Self {
+            nodes,
+            prefs: targets,
+            applicants: prefs.waitlist.into_iter().collect(),
+            selection,
+        })
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(mut self) -> Result<Outcome<V>, TTCError> {
+        let n = self.nodes.len();
+        let mut removed = vec![false; n];
+        let mut owned = vec![true; n];
+        let mut cursor = vec![0usize; n];
+        let mut cycles = vec![];
+        let mut chains = vec![];
+        let mut waitlisted = vec![];
+
+        loop {
+            // The top remaining choice of every owner, or the waitlist for items without one
+            let mut pointer = vec![Target::Waitlist; n];
+            for v in (0..n).filter(|&v| !removed[v] && owned[v]) {
+                let prefs = &self.prefs[v];
+                while let Some(Target::Item(x)) = prefs.get(cursor[v]) {
+                    if !removed[*x] {
+                        break;
+                    }
+                    cursor[v] += 1;
+                }
+                pointer[v] = prefs.get(cursor[v]).copied().unwrap_or(Target::Item(v));
+            }
+
+            let found = find_cycles(&pointer, &removed);
+            if !found.is_empty() {
+                for cycle in found {
+                    for &v in &cycle {
+                        removed[v] = true;
+                    }
+                    cycles.push(Cycle {
+                        values: cycle.into_iter().map(|v| self.nodes[v].clone()).collect(),
+                    });
+                }
+                continue;
+            }
+
+            let Some(chain) = self.select_chain(&pointer, &removed, &owned) else {
+                break;
+            };
+            let (&head, rest) = chain.split_first().ok_or(TTCError::AlwaysCycles)?;
+            if rest.is_empty() {
+                removed[head] = true;
+                waitlisted.push((self.nodes[head].clone(), self.applicants.pop_front()));
+                continue;
+            }
+            for &v in rest {
+                removed[v] = true;
+            }
+            owned[head] = false;
+            chains.push(Chain {
+                values: chain.into_iter().map(|v| self.nodes[v].clone()).collect(),
+            });
+        }
+
+        Ok(Outcome {
+            cycles,
+            chains,
+            waitlisted,
+        })
+    }
+
+    fn select_chain(
+        &self,
+        pointer: &[Target],
+        removed: &[bool],
+        owned: &[bool],
+    ) -> Option<Vec<usize>> {
+        let n = self.nodes.len();
+        let remaining = || (0..n).filter(|&v| !removed[v]);
+        let start = match &self.selection {
+            ChainSelection::Shortest => remaining().find(|&v| pointer[v] == Target::Waitlist),
+            ChainSelection::Longest => {
+                // Without cycles the pointers form a forest rooted at the waitlist, so the
+                // lengths can be filled in by following each chain until a known length
+                let mut length: Vec<Option<usize>> = vec![None; n];
+                for start in remaining() {
+                    let mut path = vec![];
+                    let mut current = start;
+                    let mut known = loop {
+                        if let Some(l) = length[current] {
+                            break l;
+                        }
+                        path.push(current);
+                        match pointer[current] {
+                            Target::Item(next) => current = next,
+                            Target::Waitlist => break 0,
+                        }
+                    };
+                    for &v in path.iter().rev() {
+                        known += 1;
+                        length[v] = Some(known);
+                    }
+                }
+                // `max_by_key` returns the last maximum, so reverse to prefer the smallest item
+                remaining().rev().max_by_key(|&v| length[v])
+            }
+            ChainSelection::Priority(order) => order
+                .iter()
+                .filter_map(|p| self.nodes.binary_search(p).ok())
+                .find(|&v| !removed[v] && owned[v])
+                .or_else(|| remaining().next()),
+        }?;
+
+        let mut chain = vec![start];
+        while let Target::Item(next) = pointer[*chain.last()?] {
+            chain.push(next);
+        }
+        Some(chain)
+    }
+}
+
+// All cycles among the remaining items, by following pointers from every item once
+fn find_cycles(pointer: &[Target], removed: &[bool]) -> Vec<Vec<usize>> {
+    let n = pointer.len();
+    // 0 = unvisited, otherwise the walk (start + 1) that first visited the item
+    let mut visited = vec![0usize; n];
+    let mut cycles = vec![];
+    for start in (0..n).filter(|&v| !removed[v]) {
+        if visited[start] != 0 {
+            continue;
+        }
+        let mut path = vec![];
+        let mut current = start;
+        loop {
+            if visited[current] != 0 {
+                if visited[current] == start + 1 {
+                    let from = path.iter().position(|&v| v == current).unwrap_or(0);
+                    cycles.push(path.split_off(from));
+                }
+                break;
+            }
+            visited[current] = start + 1;
+            path.push(current);
+            match pointer[current] {
+                Target::Item(next) => current = next,
+                Target::Waitlist => break,
+            }
+        }
+    }
+    cycles
+}
+
+#[cfg(any(test, feature = "test"))]
+pub mod test_utils {
+    use super::*;
+    use crate::test_utils;
+    use proptest::prelude::*;
+
+    impl<V> Arbitrary for Preferences<V>
+    where
+        V: Clone + Eq + Hash + core::fmt::Debug + Arbitrary + 'static,
+        V::Strategy: 'static,
+    {
+        type Parameters = Option<core::ops::RangeInclusive<usize>>;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(params: Self::Parameters) -> Self::Strategy {
+            test_utils::strict_with(params, |prefs| {
+                let len = prefs.prefs.len();
+                // where to put the waitlist in each list, if at all
+                let waitlist_positions =
+                    prop::collection::vec(prop::option::of(any::<prop::sample::Index>()), len);
+                let applicants = prop::collection::vec(any::<V>(), 0..=len);
+                (waitlist_positions, applicants)
+            })
+            .prop_map(|(prefs, (positions, applicants))| {
+                let waitlist = applicants
+                    .into_iter()
+                    .filter(|a| !prefs.prefs.contains_key(a))
+                    .fold(vec![], |mut acc, a| {
+                        if !acc.contains(&a) {
+                            acc.push(a);
+                        }
+                        acc
+                    });
+                let prefs = prefs
+                    .prefs
+                    .into_iter()
+                    .zip(positions)
+                    .map(|((k, vs), position)| {
+                        let mut choices: Vec<Choice<V>> =
+                            vs.into_iter().map(Choice::Item).collect();
+                        if let Some(ix) = position {
+                            let ix = ix.index(choices.len() + 1);
+                            choices.insert(ix, Choice::Waitlist);
+                        }
+                        (k, choices)
+                    })
+                    .collect();
+                Preferences { prefs, waitlist }
+            })
+            .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::canonical_cycles;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    fn example() -> Preferences<u32> {
+        // 3 donates its item, and 1 and 2 would rather give theirs away than keep them
+        let prefs = vec![
+            (1, vec![Choice::Item(2), Choice::Waitlist]),
+            (2, vec![Choice::Item(3), Choice::Waitlist]),
+            (3, vec![Choice::Waitlist]),
+        ];
+        Preferences::new(prefs.into_iter().collect(), vec![10, 11]).unwrap()
+    }
+
+    #[test]
+    fn longest_chain_test() {
+        let outcome = PreferenceGraph::new(example(), ChainSelection::Longest)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        assert!(outcome.cycles.is_empty());
+        // 1 gets 2's item, 2 gets 3's and the first applicant gets 1's
+        assert_eq!(
+            outcome.chains,
+            vec![Chain {
+                values: vec![1, 2, 3]
+            }]
+        );
+        assert_eq!(outcome.waitlisted, vec![(1, Some(10))]);
+        assert_eq!(
+            outcome.allocation().allocation,
+            vec![(1, 2), (2, 3), (10, 1)].into_iter().collect()
+        );
+    }
+
+    #[test]
+    fn shortest_chain_test() {
+        let outcome = PreferenceGraph::new(example(), ChainSelection::Shortest)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        // 3's item goes straight to the waitlist, then 2 and then 1 give up theirs
+        assert!(outcome.chains.is_empty());
+        assert_eq!(
+            outcome.waitlisted,
+            vec![(3, Some(10)), (2, Some(11)), (1, None)]
+        );
+        assert_eq!(outcome.unclaimed(), vec![&1]);
+    }
+
+    #[test]
+    fn priority_chain_test() {
+        let outcome = PreferenceGraph::new(example(), ChainSelection::Priority(vec![2]))
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        let chains: Vec<Vec<u32>> = outcome.chains.iter().map(|c| c.values.clone()).collect();
+        // 2 goes first and takes 3's item, leaving its own item for 1
+        assert_eq!(chains, vec![vec![2, 3], vec![1, 2]]);
+        assert_eq!(outcome.waitlisted, vec![(1, Some(10))]);
+    }
+
+    fn received(outcome: &Outcome<u32>, p: &Preferences<u32>) -> HashMap<u32, Choice<u32>> {
+        let alloc = outcome.allocation();
+        p.prefs
+            .keys()
+            .map(|&v| {
+                let choice = alloc
+                    .allocation
+                    .get(&v)
+                    .map(|&x| Choice::Item(x))
+                    .unwrap_or(Choice::Waitlist);
+                (v, choice)
+            })
+            .collect()
+    }
+
+    proptest! {
+    #[test]
+    fn test_can_solve_random_graph(
+        p in Preferences::<u32>::arbitrary(),
+        selection in prop_oneof![
+            Just(ChainSelection::Shortest),
+            Just(ChainSelection::Longest),
+            any::<Vec<u32>>().prop_map(ChainSelection::Priority),
+        ])
+      { let outcome = PreferenceGraph::new(p.clone(), selection).unwrap().solve_preferences().unwrap();
+
+        // Check that every item is traded exactly once
+        let mut items: Vec<u32> = outcome.cycles.iter().flat_map(|c| c.values.clone()).collect();
+        for chain in &outcome.chains {
+            // the head's item stays in the market
+            items.extend(&chain.values[1..]);
+        }
+        items.extend(outcome.waitlisted.iter().map(|(item, _)| *item));
+        let traded: HashSet<u32> = items.iter().cloned().collect();
+        prop_assert_eq!(traded.len(), items.len(), "Some item was traded twice");
+        prop_assert_eq!(traded, p.prefs.keys().cloned().collect::<HashSet<u32>>());
+
+        // Check that applicants are served in order
+        let served: Vec<u32> = outcome.waitlisted.iter().filter_map(|(_, a)| *a).collect();
+        prop_assert_eq!(&served[..], &p.waitlist[..served.len()]);
+
+        // Check that everyone does at least as well as keeping their item
+        let received = received(&outcome, &p);
+        for (v, choice) in &received {
+            prop_assert!(p.acceptable_rank(v, choice).is_some(), "{} received {:?}", v, choice);
+        }
+
+        // Check that no two participants would rather swap what they received
+        for (a, x) in &received {
+            for (b, y) in &received {
+                let rank = |v, c| p.acceptable_rank(v, c).unwrap_or(usize::MAX);
+                let exchange = a != b
+                    && x != y
+                    && y != &Choice::Waitlist
+                    && x != &Choice::Waitlist
+                    && rank(a, y) < rank(a, x)
+                    && rank(b, x) < rank(b, y);
+                prop_assert!(!exchange, "{} and {} would rather swap", a, b);
+            }
+        }
+      }
+
+    #[test]
+    fn test_matches_strict_without_waitlist(p in strict::Preferences::<u32>::arbitrary())
+      { let outcome = PreferenceGraph::new(Preferences::from(p.clone()), ChainSelection::Longest)
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        prop_assert!(outcome.chains.is_empty());
+        let expected = strict::PreferenceGraph::new(p).unwrap().solve_canonical().unwrap();
+        prop_assert_eq!(canonical_cycles(outcome.cycles), expected);
+      }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::strict::{Allocation, Preferences};
+use hashbrown::{HashMap, HashSet};
+use petgraph::{algo::tarjan_scc, graph::DiGraph};
+use std::{collections::VecDeque, hash::Hash};
+
+/// The result of checking an allocation against a preference profile. Every list is sorted, and
+/// an allocation is valid iff all of them are empty and there is no blocking coalition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report<V> {
+    /// Participants that aren't allocated anything
+    pub unallocated: Vec<V>,
+    /// Allocation entries for participants that aren't in the pool
+    pub unknown_participants: Vec<V>,
+    /// Items that aren't in the pool, or are allocated more than once
+    pub invalid_items: Vec<V>,
+    /// Participants that receive an item they rank below their own
+    pub not_individually_rational: Vec<V>,
+    /// Trades `(participant, item)` where the item isn't on the participant's list
+    pub unlisted_trades: Vec<(V, V)>,
+    /// A cycle of participants that would all do at least as well, and at least one strictly
+    /// better, by trading their own items among themselves: each one gets the next one's item.
+    pub blocking_coalition: Option<Vec<V>>,
+}
+
+impl<V> Report<V> {
+    pub fn is_valid(&self) -> bool {
+        self.unallocated.is_empty()
+            && self.unknown_participants.is_empty()
+            && self.invalid_items.is_empty()
+            && self.not_individually_rational.is_empty()
+            && self.unlisted_trades.is_empty()
+            && self.blocking_coalition.is_none()
+    }
+}
+
+impl<V> Allocation<V>
+where
+    V: Eq + Hash + Clone + Ord,
+{
+    /// Certify the allocation against `prefs` without re-running the solver. Besides checking that
+    /// it's a reallocation of the deposited items that respects everyone's lists, this checks that
+    /// the allocation is in the core: no group of participants can do better by trading among
+    /// themselves. For strict preferences the TTC allocation is the only one that passes.
+    pub fn verify(&self, prefs: &Preferences<V>) -> Report<V> {
+        let mut unallocated: Vec<V> = prefs
+            .prefs
+            .keys()
+            .filter(|v| !self.allocation.contains_key(*v))
+            .cloned()
+            .collect();
+        unallocated.sort();
+
+        let mut unknown_participants: Vec<V> = self
+            .allocation
+            .keys()
+            .filter(|v| !prefs.prefs.contains_key(*v))
+            .cloned()
+            .collect();
+        unknown_participants.sort();
+
+        let mut invalid_items: Vec<V> = {
+            let mut seen = HashSet::new();
+            self.allocation
+                .values()
+                .filter(|v| !prefs.prefs.contains_key(*v) || !seen.insert(*v))
+                .cloned()
+                .collect::<HashSet<V>>()
+                .into_iter()
+                .collect()
+        };
+        invalid_items.sort();
+
+        let mut not_individually_rational = Vec::new();
+        let mut unlisted_trades = Vec::new();
+        for (participant, item) in &self.allocation {
+            let Some(list) = prefs.get(participant) else {
+                continue;
+            };
+            if participant != item && !list.contains(item) {
+                unlisted_trades.push((participant.clone(), item.clone()));
+            }
+            if prefs.acceptable_rank(participant, item).is_none() {
+                not_individually_rational.push(participant.clone());
+            }
+        }
+        not_individually_rational.sort();
+        unlisted_trades.sort();
+
+        Report {
+            unallocated,
+            unknown_participants,
+            invalid_items,
+            not_individually_rational,
+            unlisted_trades,
+            blocking_coalition: self.find_blocking_coalition(prefs),
+        }
+    }
+
+    // Participant `a` points at `b` if it likes `b`'s own item at least as much as what it's
+    // allocated. A coalition blocks iff some strict edge lies on a cycle of these edges, i.e.
+    // both of its ends are in the same strongly connected component.
+    fn find_blocking_coalition(&self, prefs: &Preferences<V>) -> Option<Vec<V>> {
+        let mut participants: Vec<&V> = prefs.prefs.keys().collect();
+        participants.sort();
+        let mut graph = DiGraph::<&V, bool>::new();
+        let nodes: HashMap<&V, _> = participants
+            .iter()
+            .map(|&v| (v, graph.add_node(v)))
+            .collect();
+
+        for &a in &participants {
+            let current = self
+                .allocation
+                .get(a)
+                .and_then(|item| prefs.acceptable_rank(a, item))
+                .unwrap_or(usize::MAX);
+            let list = &prefs.prefs[a];
+            for b in list.iter().chain(std::iter::once(a)) {
+                let Some(rank) = prefs.acceptable_rank(a, b) else {
+                    continue;
+                };
+                if rank <= current && nodes.contains_key(b) {
+                    graph.update_edge(nodes[a], nodes[b], rank < current);
+                }
+            }
+        }
+
+        let component: HashMap<_, usize> = tarjan_scc(&graph)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(c, ixs)| ixs.into_iter().map(move |ix| (ix, c)))
+            .collect();
+        let edge = graph
+            .edge_indices()
+            .filter(|&e| graph[e])
+            .filter_map(|e| graph.edge_endpoints(e))
+            .find(|(a, b)| component[a] == component[b])?;
+
+        // Close the cycle with a shortest path from `b` back to `a`
+        let (a, b) = edge;
+        let mut predecessor = HashMap::new();
+        predecessor.insert(b, b);
+        let mut queue = VecDeque::from([b]);
+        while let Some(n) = queue.pop_front() {
+            if n == a {
+                break;
+            }
+            for m in graph.neighbors(n) {
+                if component[&m] == component[&a] && !predecessor.contains_key(&m) {
+                    predecessor.insert(m, n);
+                    queue.push_back(m);
+                }
+            }
+        }
+        let mut path = vec![];
+        let mut n = a;
+        while n != b {
+            n = predecessor[&n];
+            path.push(graph[n].clone());
+        }
+        let mut coalition = vec![graph[a].clone()];
+        if a != b {
+            coalition.extend(path.into_iter().rev());
+        }
+        Some(coalition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::{Cycle, PreferenceGraph};
+    use proptest::prelude::*;
+
+    #[test]
+    fn detects_blocking_coalition() {
+        let prefs = vec![(1, vec![2]), (2, vec![3, 1]), (3, vec![1])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        // 1 and 2 swap, leaving 3 with its own item
+        let alloc = Allocation::from(vec![
+            Cycle { values: vec![1, 2] },
+            Cycle { values: vec![3] },
+        ]);
+        let report = alloc.verify(&prefs);
+        // 2 and 3 are better off trading with each other, and 1 is no worse off
+        assert_eq!(report.blocking_coalition, Some(vec![2, 3, 1]));
+        assert!(!report.is_valid());
+
+        let solution = PreferenceGraph::new(prefs.clone())
+            .unwrap()
+            .solve_preferences()
+            .unwrap();
+        assert!(Allocation::from(solution).verify(&prefs).is_valid());
+    }
+
+    #[test]
+    fn detects_invalid_allocation() {
+        let prefs = vec![(1, vec![2]), (2, vec![1]), (3, vec![])];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let alloc = Allocation {
+            allocation: vec![(1, 2), (2, 3), (4, 2)].into_iter().collect(),
+        };
+        let report = alloc.verify(&prefs);
+        assert_eq!(report.unallocated, vec![3]);
+        assert_eq!(report.unknown_participants, vec![4]);
+        assert_eq!(report.invalid_items, vec![2]);
+        assert_eq!(report.not_individually_rational, vec![2]);
+        assert_eq!(report.unlisted_trades, vec![(2, 3)]);
+    }
+
+    proptest! {
+    #[test]
+    fn test_only_ttc_allocation_is_valid(
+        (p, permutation) in Preferences::<u32>::arbitrary().prop_flat_map(|p| {
+            let participants: Vec<u32> = p.prefs.keys().cloned().collect();
+            (Just(p), Just(participants).prop_shuffle())
+        }))
+      { let ttc = Allocation::from(PreferenceGraph::new(p.clone()).unwrap().solve_preferences().unwrap());
+        let report = ttc.verify(&p);
+        prop_assert!(report.is_valid(), "TTC allocation failed verification: {:?}", report);
+
+        let mut participants: Vec<u32> = p.prefs.keys().cloned().collect();
+        participants.sort();
+        let alloc = Allocation { allocation: participants.into_iter().zip(permutation).collect() };
+        let report = alloc.verify(&p);
+        if report.is_valid() {
+            prop_assert_eq!(&alloc.allocation, &ttc.allocation);
+        }
+
+        // A blocking coalition must actually block
+        if let Some(coalition) = report.blocking_coalition {
+            let mut strictly_better = false;
+            for (a, b) in coalition.iter().zip(coalition.iter().cycle().skip(1)) {
+                let current = p.acceptable_rank(a, &alloc.allocation[a]).unwrap_or(usize::MAX);
+                let rank = p.acceptable_rank(a, b);
+                prop_assert!(rank.is_some_and(|r| r <= current), "{} doesn't want {}", a, b);
+                strictly_better |= rank.unwrap() < current;
+            }
+            prop_assert!(strictly_better);
+        }
+      }
+    }
+}
@@ -0,0 +1,484 @@
+use crate::strict::{self, Cycle, PrefsError, TTCError};
+use hashbrown::HashMap;
+use petgraph::{algo::tarjan_scc, graph::DiGraph};
+use std::{collections::VecDeque, fmt::Display, hash::Hash};
+#[cfg(feature = "tracing")]
+use tracing::instrument;
+
+/// Preferences with indifference classes: each participant ranks a list of classes, and is
+/// equally happy with any option within a class.
+#[derive(Debug, Clone)]
+pub struct Preferences<V> {
+    pub prefs: HashMap<V, Vec<Vec<V>>>,
+}
+
+impl<V> Preferences<V> {
+    pub fn participants(&self) -> Vec<&V> {
+        self.prefs.keys().collect()
+    }
+}
+
+impl<V: Eq + Hash> Preferences<V> {
+    /// The index of the indifference class containing `value`, or `usize::MAX` if the
+    /// participant doesn't rank it.
+    pub fn rank(&self, participant: V, value: V) -> Option<usize> {
+        self.prefs.get(&participant).map(|classes| {
+            classes
+                .iter()
+                .position(|class| class.contains(&value))
+                .unwrap_or(usize::MAX)
+        })
+    }
+
+    pub fn get(&self, v: &V) -> Option<&Vec<Vec<V>>> {
+        self.prefs.get(v)
+    }
+}
+
+impl<V> Preferences<V>
+where
+    V: Display + Eq + Hash + Clone,
+{
+    pub fn new(prefs: HashMap<V, Vec<Vec<V>>>) -> Result<Self, PrefsError<V>> {
+        for (k, classes) in prefs.iter() {
+            if !classes.iter().flatten().all(|a| prefs.contains_key(a)) {
+                return Err(PrefsError::InvalidChoice(k.clone()));
+            }
+        }
+        Ok(Self { prefs })
+    }
+}
+
+impl<V: Eq + Hash> From<strict::Preferences<V>> for Preferences<V> {
+    fn from(prefs: strict::Preferences<V>) -> Self {
+        Preferences {
+            prefs: prefs
+                .prefs
+                .into_iter()
+                .map(|(k, vs)| (k, vs.into_iter().map(|v| vec![v]).collect()))
+                .collect(),
+        }
+    }
+}
+
+/// Top trading cycles for weak preferences, following Jaramillo & Manjunath (2012),
+/// "The difference indifference makes in strategy-proof allocation of objects".
+///
+/// Participants are prioritised by the `Ord` instance of `V`, which is used to break ties
+/// between equally preferred options.
+pub struct PreferenceGraph<V> {
+    agents: Vec<V>,
+    // For each agent, its acceptable indifference classes (as agent indices), truncated after
+    // the class containing its own item.
+    classes: Vec<Vec<Vec<usize>>>,
+}
+
+impl<V> PreferenceGraph<V>
+where
+    V: Ord + Display + Hash + Clone,
+{
+    pub fn new(prefs: Preferences<V>) -> Result<Self, TTCError> {
+        let mut agents: Vec<V> = prefs.prefs.keys().cloned().collect();
+        if agents.is_empty() {
+            return Err(TTCError::EmptyGraph);
+        }
+        agents.sort();
+        let index: HashMap<V, usize> = agents
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.clone(), i))
+            .collect();
+
+        let classes = agents
+            .iter()
+            .enumerate()
+            .map(|(me, v)| {
+                let mut seen = vec![false; agents.len()];
+                let mut classes = Vec::new();
+                for class in &prefs.prefs[v] {
+                    let mut ixs = Vec::new();
+                    for choice in class {
+                        let ix = *index
+                            .get(choice)
+                            .ok_or_else(|| TTCError::InvalidEdge(format!("{} -> {}", v, choice)))?;
+                        if !seen[ix] {
+                            seen[ix] = true;
+                            ixs.push(ix);
+                        }
+                    }
+                    if !ixs.is_empty() {
+                        classes.push(ixs);
+                    }
+                    if seen[me] {
+                        break;
+                    }
+                }
+                if !seen[me] {
+                    classes.push(vec![me]);
+                }
+                Ok(classes)
+            })
+            .collect::<Result<_, TTCError>>()?;
+
+        Ok(Self { agents, classes })
+    }
+
+    #[cfg_attr(feature = "tracing", instrument(skip_all, level = "info"))]
+    pub fn solve_preferences(&self) -> Result<Vec<Cycle<V>>, TTCError> {
+        let n = self.agents.len();
+        // Items are identified with the agent that deposited them.
+        let mut holding: Vec<usize> = (0..n).collect();
+        let mut holder: Vec<usize> = (0..n).collect();
+        let mut active = vec![true; n];
+        let mut target: Vec<Option<usize>> = vec![None; n];
+
+        while active.iter().any(|&a| a) {
+            let agents: Vec<usize> = (0..n).filter(|&i| active[i]).collect();
+            let top: HashMap<usize, &Vec<usize>> = agents
+                .iter()
+                .map(|&i| {
+                    let class = self.classes[i]
+                        .iter()
+                        .find(|class| class.iter().any(|&o| active[holder[o]]))
+                        .ok_or(TTCError::AlwaysCycles)?;
+                    Ok((i, class))
+                })
+                .collect::<Result<_, TTCError>>()?;
+            let available = |o: &&usize| active[holder[**o]];
+            let satisfied = |i: usize| top[&i].contains(&holding[i]);
+
+            let mut graph = DiGraph::<usize, ()>::new();
+            let nodes: HashMap<usize, _> = agents.iter().map(|&i| (i, graph.add_node(i))).collect();
+            for &i in &agents {
+                for o in top[&i].iter().filter(available) {
+                    graph.add_edge(nodes[&i], nodes[&holder[*o]], ());
+                }
+            }
+
+            let mut leaving = Vec::new();
+            let mut trades = Vec::new();
+            for scc in tarjan_scc(&graph) {
+                let members: Vec<usize> = scc.iter().map(|&ix| graph[ix]).collect();
+                let terminal = members.iter().all(|&i| {
+                    top[&i]
+                        .iter()
+                        .filter(available)
+                        .all(|&o| members.contains(&holder[o]))
+                });
+                if !terminal {
+                    continue;
+                }
+
+                if members.iter().all(|&i| satisfied(i)) {
+                    leaving.extend(members);
+                    continue;
+                }
+
+                // Distance from each member to the nearest unsatisfied member, so that satisfied
+                // agents only ever point towards someone who still wants to trade.
+                let mut distance: HashMap<usize, usize> = HashMap::new();
+                let mut queue: VecDeque<usize> = VecDeque::new();
+                for &i in members.iter().filter(|&&i| !satisfied(i)) {
+                    distance.insert(i, 0);
+                    queue.push_back(i);
+                }
+                while let Some(j) = queue.pop_front() {
+                    for &i in &members {
+                        let points_at_j = top[&i].iter().filter(available).any(|&o| holder[o] == j);
+                        if points_at_j && !distance.contains_key(&i) {
+                            distance.insert(i, distance[&j] + 1);
+                            queue.push_back(i);
+                        }
+                    }
+                }
+
+                let mut points_to: HashMap<usize, usize> = HashMap::new();
+                for &i in &members {
+                    let choice = if satisfied(i) {
+                        *top[&i]
+                            .iter()
+                            .filter(available)
+                            .min_by_key(|&&o| (distance[&holder[o]], o))
+                            .ok_or(TTCError::AlwaysCycles)?
+                    } else {
+                        match target[i] {
+                            Some(o) if top[&i].contains(&o) && active[holder[o]] => o,
+                            _ => *top[&i]
+                                .iter()
+                                .filter(available)
+                                .min()
+                                .ok_or(TTCError::AlwaysCycles)?,
+                        }
+                    };
+                    target[i] = Some(choice);
+                    points_to.insert(i, choice);
+                }
+
+                // Every agent points at exactly one other member, so following the pointers from
+                // any member eventually reaches a cycle.
+                let mut visited: HashMap<usize, bool> = HashMap::new();
+                for &start in &members {
+                    let mut path = Vec::new();
+                    let mut current = start;
+                    while !visited.contains_key(&current) {
+                        visited.insert(current, false);
+                        path.push(current);
+                        current = holder[points_to[&current]];
+                    }
+                    if visited[&current] {
+                        path.iter().for_each(|&i| {
+                            visited.insert(i, true);
+                        });
+                        continue;
+                    }
+                    let from = path.iter().position(|&i| i == current).unwrap();
+                    let cycle = path[from..].to_vec();
+                    trades.extend(cycle.iter().map(|&i| (i, points_to[&i])));
+                    path.iter().for_each(|&i| {
+                        visited.insert(i, true);
+                    });
+                }
+            }
+
+            leaving.into_iter().for_each(|i| active[i] = false);
+            for (i, o) in trades {
+                holding[i] = o;
+                holder[o] = i;
+                target[i] = None;
+            }
+        }
+
+        let mut seen = vec![false; n];
+        let mut cycles = Vec::new();
+        for start in 0..n {
+            if seen[start] {
+                continue;
+            }
+            let mut values = Vec::new();
+            let mut current = start;
+            while !seen[current] {
+                seen[current] = true;
+                values.push(self.agents[current].clone());
+                current = holding[current];
+            }
+            cycles.push(Cycle { values });
+        }
+        Ok(cycles)
+    }
+}
+
+#[cfg(any(test, feature = "test"))]
+pub mod test_utils {
+    use super::*;
+    use crate::test_utils;
+    use proptest::prelude::*;
+
+    impl<V> Arbitrary for Preferences<V>
+    where
+        V: Clone + Eq + std::hash::Hash + std::fmt::Debug + Arbitrary + 'static,
+        V::Strategy: 'static,
+    {
+        type Parameters = Option<std::ops::RangeInclusive<usize>>;
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(params: Self::Parameters) -> Self::Strategy {
+            // for each entry after the first, decide whether it starts a new class or ties
+            // with the one before it. No list is longer than the number of participants.
+            test_utils::strict_with(params, |prefs| {
+                let len = prefs.prefs.len();
+                prop::collection::vec(prop::collection::vec(any::<bool>(), len), len)
+            })
+            .prop_map(|(prefs, splits)| Preferences {
+                prefs: prefs
+                    .prefs
+                    .into_iter()
+                    .zip(splits)
+                    .map(|((v, vs), splits)| {
+                        let mut classes: Vec<Vec<V>> = Vec::new();
+                        for (choice, split) in vs.into_iter().zip(splits) {
+                            match classes.last_mut() {
+                                Some(class) if !split => class.push(choice),
+                                _ => classes.push(vec![choice]),
+                            }
+                        }
+                        (v, classes)
+                    })
+                    .collect(),
+            })
+            .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::strict::Allocation;
+    use proptest::prelude::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn basic_test() {
+        // 1 is indifferent between 2 and 3, so it should take 3 and let 3 take 2
+        let prefs = vec![
+            (1, vec![vec![2, 3]]),
+            (2, vec![vec![1]]),
+            (3, vec![vec![2]]),
+        ];
+        let prefs = Preferences::new(prefs.into_iter().collect()).unwrap();
+        let g = PreferenceGraph::new(prefs).unwrap();
+        let alloc = Allocation::from(g.solve_preferences().unwrap());
+        assert_eq!(
+            alloc.allocation,
+            vec![(1, 3), (2, 1), (3, 2)].into_iter().collect()
+        );
+    }
+
+    // The index of the class containing `item`, or `None` if it's worse than the participant's
+    // own item.
+    fn acceptable_rank(prefs: &Preferences<u32>, participant: u32, item: u32) -> Option<usize> {
+        let classes = &prefs.prefs[&participant];
+        let own = classes
+            .iter()
+            .position(|c| c.contains(&participant))
+            .unwrap_or(classes.len());
+        if item == participant {
+            return Some(own);
+        }
+        classes
+            .iter()
+            .position(|c| c.contains(&item))
+            .filter(|&r| r <= own)
+    }
+
+    // An allocation is Pareto dominated iff some participant strictly prefers an item that can be
+    // reached by a cycle of participants who each weakly prefer the next participant's item.
+    fn find_pareto_improvement(
+        prefs: &Preferences<u32>,
+        alloc: &Allocation<u32>,
+    ) -> Option<(u32, u32)> {
+        let mut graph = DiGraph::<u32, ()>::new();
+        let nodes: HashMap<u32, _> = alloc
+            .allocation
+            .keys()
+            .map(|&k| (k, graph.add_node(k)))
+            .collect();
+        let mut strict_edges = vec![];
+        for (&i, &held) in &alloc.allocation {
+            let current = acceptable_rank(prefs, i, held).unwrap();
+            for (&j, &other) in &alloc.allocation {
+                match acceptable_rank(prefs, i, other) {
+                    Some(r) if r < current => strict_edges.push((i, j)),
+                    Some(r) if r == current => {}
+                    _ => continue,
+                }
+                graph.add_edge(nodes[&i], nodes[&j], ());
+            }
+        }
+        let component: HashMap<u32, usize> = tarjan_scc(&graph)
+            .into_iter()
+            .enumerate()
+            .flat_map(|(c, ixs)| ixs.into_iter().map(move |ix| (ix, c)))
+            .map(|(ix, c)| (graph[ix], c))
+            .collect();
+        strict_edges
+            .into_iter()
+            .find(|(i, j)| component[i] == component[j])
+    }
+
+    // The reports a participant could make instead of `classes`: every truncation, every merge
+    // of two adjacent classes, every swap of two classes and every single drop of an option.
+    fn misreports(classes: &[Vec<u32>]) -> Vec<Vec<Vec<u32>>> {
+        let n = classes.len();
+        let mut reports: Vec<Vec<Vec<u32>>> = (0..n).map(|k| classes[..k].to_vec()).collect();
+        for i in 0..n.saturating_sub(1) {
+            let mut report = classes.to_vec();
+            let next = report.remove(i + 1);
+            report[i].extend(next);
+            reports.push(report);
+        }
+        for i in 0..n {
+            for j in i + 1..n {
+                let mut report = classes.to_vec();
+                report.swap(i, j);
+                reports.push(report);
+            }
+        }
+        for (i, class) in classes.iter().enumerate() {
+            for j in 0..class.len() {
+                let mut report = classes.to_vec();
+                report[i].remove(j);
+                report.retain(|c| !c.is_empty());
+                reports.push(report);
+            }
+        }
+        reports
+    }
+
+    fn received(prefs: &Preferences<u32>, participant: u32) -> u32 {
+        let g = PreferenceGraph::new(prefs.clone()).unwrap();
+        Allocation::from(g.solve_preferences().unwrap()).allocation[&participant]
+    }
+
+    proptest! {
+    #[test]
+    fn test_can_solve_random_graph(p in Preferences::<u32>::arbitrary())
+      { let g = PreferenceGraph::new(p.clone()).unwrap();
+        let solution = g.solve_preferences();
+        prop_assert!(solution.is_ok(), "Unsolvable graph");
+        let alloc = Allocation::from(solution.unwrap());
+
+        // Check that the allocation is a reallocation of the deposited items
+        {
+            let participants: HashSet<u32> = p.participants().into_iter().cloned().collect();
+            let owners: HashSet<u32> = alloc.allocation.keys().cloned().collect();
+            let items: HashSet<u32> = alloc.allocation.values().cloned().collect();
+            prop_assert_eq!(&participants, &owners, "Not all participants were assigned");
+            prop_assert_eq!(&participants, &items, "Not all items were assigned");
+        }
+
+        // Check that nobody is worse off than with their own item
+        alloc.allocation.iter().try_for_each(|(&k, &v)| {
+            prop_assert!(acceptable_rank(&p, k, v).is_some(), "{} received unacceptable item {}", k, v);
+            Ok(())
+        })?;
+
+        // Check that the allocation is Pareto efficient
+        let improvement = find_pareto_improvement(&p, &alloc);
+        prop_assert!(improvement.is_none(), "Allocation can be improved along {:?}", improvement);
+      }
+
+    #[test]
+    fn test_agrees_with_strict_preferences(p in strict::Preferences::<u32>::arbitrary())
+      { let strict_alloc = {
+            let mut g = strict::PreferenceGraph::new(p.clone()).unwrap();
+            Allocation::from(g.solve_preferences().unwrap())
+        };
+        let weak_alloc = {
+            let g = PreferenceGraph::new(Preferences::from(p)).unwrap();
+            Allocation::from(g.solve_preferences().unwrap())
+        };
+        prop_assert_eq!(strict_alloc.allocation, weak_alloc.allocation);
+      }
+
+    #[test]
+    fn test_strategy_proof(p in Preferences::<u32>::arbitrary_with(Some(2..=5)))
+      { let value = |participant: u32, item: u32| {
+            acceptable_rank(&p, participant, item).unwrap_or(usize::MAX)
+        };
+        for &participant in p.prefs.keys() {
+            let truthful = received(&p, participant);
+            for report in misreports(&p.prefs[&participant]) {
+                let mut lie = p.clone();
+                lie.prefs.insert(participant, report.clone());
+                let item = received(&lie, participant);
+                prop_assert!(
+                    value(participant, item) >= value(participant, truthful),
+                    "{} gets {} by reporting {:?} instead of {}", participant, item, report, truthful
+                );
+            }
+        }
+      }
+    }
+}